@@ -1,229 +1,183 @@
-use anyhow::Context;
+use anyhow::Context as _;
+use maelstrom_core::{Context, Error, Handler, Logger, Message, RpcOptions, Topology, debug};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
-    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use crate::topologies::Topology;
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Message {
-    pub src: String,
-    pub dest: String,
-    pub body: MessageBody,
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum MessageBody {
-    Init {
-        msg_id: u32,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk {
-        in_reply_to: u32,
-    },
     Broadcast {
-        msg_id: u32,
         message: i32,
     },
-    BroadcastOk {
-        in_reply_to: u32,
-    },
-    Read {
-        msg_id: u32,
-    },
+    BroadcastOk,
+    Read,
     ReadOk {
         messages: HashSet<i32>,
-        in_reply_to: u32,
     },
     Topology {
-        msg_id: u32,
         topology: HashMap<String, Vec<String>>,
     },
-    TopologyOk {
-        in_reply_to: u32,
-    },
+    TopologyOk,
     Gossip {
-        msg_id: u32,
         messages: HashSet<i32>,
     },
     GossipOk {
-        in_reply_to: u32,
         messages: HashSet<i32>,
     },
 }
 
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Debug, Default, Clone)]
-pub struct NodeState {
+pub struct Broadcast {
     pub messages: HashSet<i32>,
     /// It represents a vector of node ids. These nodes will be used for gossiping.
     pub neighbors: Vec<String>,
-    pub last_message_id: u32,
     pub pending_to_send: HashSet<i32>,
 }
 
-#[derive(Debug)]
-pub struct Node {
-    pub node_id: String,
-    pub node_ids: Vec<String>,
-    pub state: Arc<Mutex<NodeState>>,
-}
+impl Broadcast {
+    /// Sends the pending messages to every neighbor.
+    fn gossip(&mut self, ctx: &mut Context<Self>) -> anyhow::Result<()> {
+        if self.pending_to_send.is_empty() {
+            return Ok(());
+        }
 
-impl Node {
-    pub fn init(line: String, state: Arc<Mutex<NodeState>>) -> anyhow::Result<Self> {
-        let msg: Message = serde_json::from_str(&line).context("Message deserialization error")?;
+        debug!(
+            ctx.log(),
+            "Gossiping {} pending messages to {:?}",
+            self.pending_to_send.len(),
+            self.neighbors
+        );
+
+        // A gossip that times out is not retried, the next tick sends the pending messages again.
+        let options = RpcOptions {
+            timeout: GOSSIP_INTERVAL,
+            retries: 0,
+        };
 
-        match msg.body.clone() {
-            MessageBody::Init {
-                msg_id,
-                node_id,
-                node_ids,
-            } => {
-                let node = Self {
-                    node_id: node_id.clone(),
-                    node_ids,
-                    state: state.clone(),
-                };
-
-                let reply = Message {
-                    src: node_id,
-                    dest: msg.src,
-                    body: MessageBody::InitOk {
-                        in_reply_to: msg_id,
-                    },
-                };
-
-                node.write(reply)?;
-
-                Ok(node)
-            }
-            _ => Err(anyhow::anyhow!(
-                "Init message is not the first message received"
-            )),
+        for neighbor in self.neighbors.iter() {
+            ctx.rpc(
+                neighbor,
+                MessageBody::Gossip {
+                    messages: self.pending_to_send.clone(),
+                },
+                options,
+                |broadcast, ctx, reply| {
+                    if let Ok(reply) = reply
+                        && let MessageBody::GossipOk { messages } = reply.body.payload
+                    {
+                        broadcast.gossip_ok(ctx.log(), messages);
+                    }
+
+                    Ok(())
+                },
+            )?;
         }
+
+        Ok(())
     }
 
-    pub fn handle(&mut self, req: Message) -> anyhow::Result<Option<Message>> {
-        let topology = Topology::StarTopology.get_topology(&self.node_ids);
+    fn gossip_ok(&mut self, logger: &Logger, external_messages: HashSet<i32>) {
+        let internal_messages = self.messages.clone();
+        // Check if there are messages missing from the node sending the gossip ok message.
+        // We just compare the current node messages (which is the one that send the
+        // gossip) with the messages arriving from the destination node.
+        //
+        // This solution assumes that gossip ok includes all messages from the destination
+        // node.
+        let lost_messages: Vec<i32> = internal_messages
+            .clone()
+            .into_iter()
+            .filter(|m| !external_messages.contains(m))
+            .collect();
+
+        if !lost_messages.is_empty() {
+            debug!(
+                logger,
+                "Peer misses {} messages, queuing them again",
+                lost_messages.len()
+            );
+        }
 
-        let body: Option<MessageBody> = match req.body.clone() {
-            MessageBody::Broadcast { msg_id, message } => {
-                let mut state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a broadcast message");
+        self.pending_to_send.extend(lost_messages);
+        self.messages.extend(external_messages);
+    }
+}
 
-                state.messages.insert(message);
-                state.pending_to_send.insert(message);
+impl Handler for Broadcast {
+    type Payload = MessageBody;
 
-                Some(MessageBody::BroadcastOk {
-                    in_reply_to: msg_id,
-                })
-            }
-            MessageBody::Read { msg_id } => {
-                let state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a broadcast message");
-
-                Some(MessageBody::ReadOk {
-                    messages: state.messages.clone(),
-                    in_reply_to: msg_id,
-                })
+    fn init(ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+        ctx.every("gossip", GOSSIP_INTERVAL, Self::gossip);
+
+        Ok(Self::default())
+    }
+
+    fn handle(
+        &mut self,
+        ctx: &mut Context<Self>,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let node = ctx.node();
+        let topology = Topology::Star.get_topology(&node.node_ids);
+
+        let body: Option<MessageBody> = match req.body.payload {
+            MessageBody::Broadcast { message } => {
+                self.messages.insert(message);
+                self.pending_to_send.insert(message);
+
+                Some(MessageBody::BroadcastOk)
             }
-            MessageBody::Topology {
-                msg_id,
-                topology: _,
-            } => {
-                let mut state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a broadcast message");
-
-                state.neighbors = topology
-                    .get(&self.node_id)
-                    .with_context(|| format!("Node {} does not have neighbors", self.node_id))?
+            MessageBody::Read => Some(MessageBody::ReadOk {
+                messages: self.messages.clone(),
+            }),
+            MessageBody::Topology { topology: _ } => {
+                self.neighbors = topology
+                    .get(&node.node_id)
+                    .with_context(|| format!("Node {} does not have neighbors", node.node_id))?
                     .to_vec();
 
-                Some(MessageBody::TopologyOk {
-                    in_reply_to: msg_id,
-                })
+                Some(MessageBody::TopologyOk)
             }
             MessageBody::Gossip {
-                msg_id,
                 messages: external_messages,
             } => {
-                let mut state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a broadcast message");
-
-                let internal_messages = state.messages.clone();
+                let internal_messages = self.messages.clone();
                 let new_messages: HashSet<i32> = internal_messages
                     .clone()
                     .into_iter()
                     .filter(|m| !external_messages.contains(m))
                     .collect();
 
+                let received = external_messages
+                    .iter()
+                    .filter(|m| !self.messages.contains(m))
+                    .count();
+
+                debug!(ctx.log(), "{} new messages from gossip", received);
+
                 // It adds to pending_gossips only the messages that the current node does not have
-                state.pending_to_send.extend(new_messages.clone());
-                state.messages.extend(external_messages);
+                self.pending_to_send.extend(new_messages.clone());
+                self.messages.extend(external_messages);
 
                 Some(MessageBody::GossipOk {
                     // We send all our messages back as part of the gossip ok response because the
                     // node that sent us that message can ensure if the message was successfully
                     // sent but comparing what we have with what they.
-                    messages: state.messages.clone(),
-                    in_reply_to: msg_id,
+                    messages: self.messages.clone(),
                 })
             }
-            MessageBody::GossipOk {
-                in_reply_to: _,
-                messages: external_messages,
-            } => {
-                let mut state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a broadcast message");
-
-                let internal_messages = state.messages.clone();
-                // Check if there are messages missing from the node sending the gossip ok message.
-                // We just compare the current node messages (which is the one that send the
-                // gossip) with the messages arriving from the destination node.
-                //
-                // This solution assumes that gossip ok includes all messages from the destination
-                // node.
-                let lost_messages = internal_messages
-                    .clone()
-                    .into_iter()
-                    .filter(|m| !external_messages.contains(m));
-
-                state.pending_to_send.extend(lost_messages);
-                state.messages.extend(external_messages);
-
-                None
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
             }
-            body => unimplemented!("Message {:?} not implemented yet", body),
         };
 
-        match body {
-            Some(b) => Ok(Some(Message {
-                src: self.node_id.clone(),
-                dest: req.src.clone(),
-                body: b,
-            })),
-            None => Ok(None),
-        }
-    }
-
-    pub fn write(&self, msg: Message) -> anyhow::Result<()> {
-        let json = serde_json::to_string(&msg).context("Message serialization error")?;
-
-        println!("{}", json);
-
-        Ok(())
+        Ok(body)
     }
 }
@@ -1,95 +1,37 @@
-use anyhow::Context;
+use maelstrom_core::{Context, Error, Handler, Message};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Message {
-    pub src: String,
-    pub dest: String,
-    pub body: MessageBody,
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum MessageBody {
-    Echo {
-        msg_id: u32,
-        echo: String,
-    },
-    EchoOk {
-        in_reply_to: u32,
-        echo: String,
-    },
-    Init {
-        msg_id: u32,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk {
-        in_reply_to: u32,
-    },
+    Echo { echo: String },
+    EchoOk { echo: String },
 }
 
 #[derive(Debug)]
-pub struct Node {
-    pub node_id: String,
-}
-
-impl Node {
-    pub fn init(line: String) -> anyhow::Result<Self> {
-        let msg: Message = serde_json::from_str(&line).context("Message deserialization error")?;
-
-        match msg.body.clone() {
-            MessageBody::Init {
-                msg_id,
-                node_id,
-                node_ids: _,
-            } => {
-                let node = Self {
-                    node_id: node_id.clone(),
-                };
-
-                let reply = Message {
-                    src: node_id,
-                    dest: msg.src,
-                    body: MessageBody::InitOk {
-                        in_reply_to: msg_id,
-                    },
-                };
+pub struct Echo;
 
-                node.write(reply)?;
+impl Handler for Echo {
+    type Payload = MessageBody;
 
-                Ok(node)
-            }
-            _ => Err(anyhow::anyhow!(
-                "Init message is not the first message received"
-            )),
-        }
+    fn init(_ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+        Ok(Self)
     }
 
-    pub fn handle(&mut self, req: Message) -> anyhow::Result<Option<Message>> {
-        let body: Option<MessageBody> = match req.body.clone() {
-            MessageBody::Echo { msg_id, echo } => Some(MessageBody::EchoOk {
-                in_reply_to: msg_id,
-                echo,
-            }),
-            body => unimplemented!("Message {:?} not implemented", body),
+    fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let body: Option<MessageBody> = match req.body.payload {
+            MessageBody::Echo { echo } => Some(MessageBody::EchoOk { echo }),
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
         };
 
-        match body {
-            Some(b) => Ok(Some(Message {
-                src: self.node_id.clone(),
-                dest: req.src.clone(),
-                body: b,
-            })),
-            None => Ok(None),
-        }
-    }
-
-    pub fn write(&self, msg: Message) -> anyhow::Result<()> {
-        let json = serde_json::to_string(&msg).context("Message serialization error")?;
-
-        println!("{}", json);
-
-        Ok(())
+        Ok(body)
     }
 }
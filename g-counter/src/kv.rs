@@ -0,0 +1,179 @@
+use maelstrom_core::{
+    Error, Message,
+    async_runtime::{Context, Handler},
+    debug,
+    services::{Kv, KvError},
+};
+use std::env;
+
+use crate::{BACKEND_ENV, node::MessageBody};
+
+/// Key holding the whole counter when it is shared by every node.
+const SHARED_KEY: &str = "counter";
+
+/// Where the counter lives in `seq-kv`.
+#[derive(Debug, Clone, Copy)]
+enum Layout {
+    /// A single key, updated by every node.
+    Shared,
+    /// A key per node, only updated by its node and summed on reads.
+    PerNode,
+}
+
+/// Counter stored in Maelstrom's `seq-kv` instead of being gossiped between nodes.
+///
+/// Adds are compare-and-swap loops: read the current value, then swap it for the new one, and
+/// start again when another add got there first.
+///
+/// `seq-kv` is only sequentially consistent, so a read may return a value from the past. Before
+/// every read, the node writes a unique value to a key of its own, which forces the store to
+/// order the read after that write, and so after every add acknowledged before it.
+#[derive(Debug)]
+pub struct KvCounter {
+    kv: Kv,
+    layout: Layout,
+}
+
+impl KvCounter {
+    fn key(&self, node_id: &str) -> String {
+        match self.layout {
+            Layout::Shared => SHARED_KEY.to_string(),
+            Layout::PerNode => format!("{}/{}", SHARED_KEY, node_id),
+        }
+    }
+
+    /// Reads the value of `key`, a key that was never written being 0.
+    async fn value(&self, ctx: &Context, key: &str) -> Result<i64, KvError> {
+        match self.kv.read(ctx, key).await {
+            Err(KvError::KeyDoesNotExist) => Ok(0),
+            result => result,
+        }
+    }
+
+    async fn add(&self, ctx: &Context, delta: i64) -> Result<(), KvError> {
+        let key = self.key(&ctx.node().node_id);
+
+        loop {
+            let value = self.value(ctx, &key).await?;
+
+            match self.kv.cas(ctx, &key, value, value + delta, true).await {
+                Err(KvError::PreconditionFailed) => {
+                    debug!(ctx.log(), "Counter changed under {}, retrying", key);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn read(&self, ctx: &Context) -> Result<i64, KvError> {
+        let node = ctx.node();
+
+        self.kv
+            .write(ctx, format!("fresh/{}", node.node_id), ctx.msg_ids().next())
+            .await?;
+
+        match self.layout {
+            Layout::Shared => self.value(ctx, SHARED_KEY).await,
+            Layout::PerNode => {
+                let mut total = 0;
+
+                for node_id in node.node_ids.iter() {
+                    total += self.value(ctx, &self.key(node_id)).await?;
+                }
+
+                Ok(total)
+            }
+        }
+    }
+}
+
+impl Handler for KvCounter {
+    type Payload = MessageBody;
+
+    fn init(_ctx: &Context) -> anyhow::Result<Self> {
+        let layout = match env::var(BACKEND_ENV).as_deref() {
+            Ok("seq-kv-per-node") => Layout::PerNode,
+            _ => Layout::Shared,
+        };
+
+        Ok(Self {
+            kv: Kv::seq(),
+            layout,
+        })
+    }
+
+    async fn handle(
+        &self,
+        ctx: Context,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let body = match req.body.payload {
+            MessageBody::Add { delta } => {
+                self.add(&ctx, delta.into()).await.map_err(Error::from)?;
+
+                MessageBody::AddOk
+            }
+            MessageBody::Read => {
+                let value = self.read(&ctx).await.map_err(Error::from)?;
+
+                MessageBody::ReadOk {
+                    value: value.try_into()?,
+                }
+            }
+            MessageBody::Topology { .. } => MessageBody::TopologyOk,
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        };
+
+        Ok(Some(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maelstrom_core::{async_runtime::run_with, harness::Cluster, harness::SeqKv};
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn reads_every_acknowledged_add_despite_stale_reads() {
+        for seed in 0..5 {
+            let cluster = Cluster::builder(3)
+                .service(SeqKv::new(seed))
+                .start(run_with::<KvCounter, _, _>)
+                .unwrap();
+
+            thread::scope(|scope| {
+                for node_id in cluster.node_ids() {
+                    let cluster = &cluster;
+
+                    scope.spawn(move || {
+                        for delta in 1..=10 {
+                            let reply: MessageBody =
+                                cluster.call(node_id, MessageBody::Add { delta }).unwrap();
+                            assert!(matches!(reply, MessageBody::AddOk));
+                        }
+                    });
+                }
+            });
+
+            for node_id in cluster.node_ids() {
+                let reply: MessageBody = cluster.call(node_id, MessageBody::Read).unwrap();
+
+                assert!(
+                    matches!(reply, MessageBody::ReadOk { value: 165 }),
+                    "Seed {}: {} read {:?}",
+                    seed,
+                    node_id,
+                    reply
+                );
+            }
+
+            cluster.stop().unwrap();
+        }
+    }
+}
@@ -1,15 +1,14 @@
-use anyhow::Context;
+use anyhow::Context as _;
+use maelstrom_core::{Context, Error, Handler, Logger, Message, RpcOptions, Topology, debug};
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, time::Duration};
 
-use crate::topologies::Topology;
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CounterValue {
-    msg_id: u32,
+    /// Incremented by the owner node on every add, so the most recent value always wins.
+    version: u32,
     value: i32,
 }
 
@@ -30,7 +29,7 @@ impl Counter {
         match self.data.get_mut(node_id) {
             Some(counter) => {
                 counter.value += new_counter.value;
-                counter.msg_id = new_counter.msg_id;
+                counter.version = new_counter.version;
             }
             None => {
                 self.data.insert(node_id.to_string(), new_counter);
@@ -40,23 +39,29 @@ impl Counter {
 
     /// It merge the value from another node counter version by taking the lastest one.
     ///
-    /// As msg_id property is an incremental unique value per node, we just check if the msg_id
-    /// from the new counter value is higher than the current one.  
+    /// As version property is an incremental unique value per node, we just check if the version
+    /// from the new counter value is higher than the current one.
     ///
     /// In case we do not have any counter for a node, we just insert it.
     ///
     /// This function is used when values are coming from a gossip, and it ensures that
-    /// the node always has the lastest values.
-    fn merge(&mut self, node_id: &str, new_counter: CounterValue) {
+    /// the node always has the lastest values. It returns whether the value changed.
+    fn merge(&mut self, node_id: &str, new_counter: CounterValue) -> bool {
         match self.data.get_mut(node_id) {
             Some(counter) => {
-                if counter.msg_id < new_counter.msg_id {
+                if counter.version < new_counter.version {
                     counter.value = new_counter.value;
-                    counter.msg_id = new_counter.msg_id;
+                    counter.version = new_counter.version;
+
+                    return true;
                 }
+
+                false
             }
             None => {
                 self.data.insert(node_id.to_string(), new_counter);
+
+                true
             }
         }
     }
@@ -73,212 +78,144 @@ impl Counter {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Message {
-    pub src: String,
-    pub dest: String,
-    pub body: MessageBody,
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum MessageBody {
-    Init {
-        msg_id: u32,
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk {
-        in_reply_to: u32,
-    },
-    Read {
-        msg_id: u32,
-    },
+    Read,
     ReadOk {
         value: i32,
-        in_reply_to: u32,
     },
     Topology {
-        msg_id: u32,
         topology: HashMap<String, Vec<String>>,
     },
-    TopologyOk {
-        in_reply_to: u32,
-    },
+    TopologyOk,
     Gossip {
-        msg_id: u32,
         counter: Counter,
     },
     GossipOk {
         counter: Counter,
-        in_reply_to: u32,
     },
     Add {
-        msg_id: u32,
         delta: i32,
     },
-    AddOk {
-        in_reply_to: u32,
-    },
-}
-
-#[derive(Debug)]
-pub enum Event {
-    // A node replies the request of a client.
-    Reply(Message),
-    // A node actively sends a message to a node or multiple nodes. An example
-    // of this event would be sending a gossip message to node's neighbors.
-    Push(Message),
-    // A node should shutdown
-    Shutdown,
-}
-
-#[derive(Debug, Default)]
-pub struct NodeState {
-    pub last_message_id: u32,
-    pub counter: Counter,
+    AddOk,
 }
 
 #[derive(Debug, Clone)]
-pub struct Node {
-    pub node_id: String,
-    pub state: Arc<Mutex<NodeState>>,
-    topology: HashMap<String, Vec<String>>,
+pub struct GCounter {
+    /// Version of this node's own counter value.
+    pub version: u32,
+    pub counter: Counter,
+    neighbors: Vec<String>,
 }
 
-impl Node {
-    pub fn init(line: String, state: Arc<Mutex<NodeState>>) -> anyhow::Result<Self> {
-        let msg: Message = serde_json::from_str(&line).context("Message deserialization error")?;
-
-        match msg.body.clone() {
-            MessageBody::Init {
-                msg_id,
-                node_id,
-                node_ids,
-            } => {
-                let node = Self {
-                    node_id: node_id.clone(),
-                    state: state.clone(),
-                    topology: Topology::RingTopology.get_topology(&node_ids),
-                };
+impl GCounter {
+    /// Sends the whole counter to every neighbor.
+    fn gossip(&mut self, ctx: &mut Context<Self>) -> anyhow::Result<()> {
+        // A gossip that times out is not retried, the next tick sends the whole counter again.
+        let options = RpcOptions {
+            timeout: GOSSIP_INTERVAL,
+            retries: 0,
+        };
 
-                let reply = Message {
-                    src: node_id,
-                    dest: msg.src,
-                    body: MessageBody::InitOk {
-                        in_reply_to: msg_id,
-                    },
-                };
+        for neighbor in self.neighbors.iter() {
+            ctx.rpc(
+                neighbor,
+                MessageBody::Gossip {
+                    counter: self.counter.clone(),
+                },
+                options,
+                |g_counter, ctx, reply| {
+                    if let Ok(reply) = reply
+                        && let MessageBody::GossipOk { counter } = reply.body.payload
+                    {
+                        g_counter.merge(ctx.log(), &ctx.node().node_id, counter);
+                    }
+
+                    Ok(())
+                },
+            )?;
+        }
 
-                node.write(reply)?;
+        Ok(())
+    }
 
-                Ok(node)
+    /// Merge all node's values with the coming ones except by itself.
+    fn merge(&mut self, logger: &Logger, own_node_id: &str, counter: Counter) {
+        for (node_id, c) in counter
+            .data
+            .into_iter()
+            .filter(|(node_id, _)| node_id != own_node_id)
+        {
+            let (version, value) = (c.version, c.value);
+
+            if self.counter.merge(&node_id, c) {
+                debug!(
+                    logger,
+                    "Counter of {} is now {} at version {}", node_id, value, version
+                );
             }
-            _ => Err(anyhow::anyhow!(
-                "Init message is not the first message received"
-            )),
         }
     }
+}
 
-    pub fn get_neighbors(&self) -> anyhow::Result<Vec<String>> {
-        self.topology
-            .get(&self.node_id)
-            .with_context(|| format!("Node {} does not have neighbors", self.node_id))
-            .cloned()
-    }
+impl Handler for GCounter {
+    type Payload = MessageBody;
 
-    pub fn handle(&mut self, req: Message) -> anyhow::Result<Option<Message>> {
-        let body: Option<MessageBody> = match req.body.clone() {
-            MessageBody::Read { msg_id } => {
-                let state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a broadcast message");
+    fn init(ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+        let node = ctx.node();
+        let neighbors = Topology::Ring
+            .get_topology(&node.node_ids)
+            .get(&node.node_id)
+            .with_context(|| format!("Node {} does not have neighbors", node.node_id))?
+            .clone();
 
-                Some(MessageBody::ReadOk {
-                    value: state.counter.sum(),
-                    in_reply_to: msg_id,
-                })
-            }
-            MessageBody::Add { msg_id, delta } => {
-                let mut state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a broadcast message");
+        ctx.every("gossip", GOSSIP_INTERVAL, Self::gossip);
 
-                let last_msg_id = state.last_message_id + 1;
+        Ok(Self {
+            version: 0,
+            counter: Counter::default(),
+            neighbors,
+        })
+    }
 
-                state.last_message_id = last_msg_id;
-                state.counter.add(
-                    &self.node_id,
+    fn handle(
+        &mut self,
+        ctx: &mut Context<Self>,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let node = ctx.node();
+
+        let body: Option<MessageBody> = match req.body.payload {
+            MessageBody::Read => Some(MessageBody::ReadOk {
+                value: self.counter.sum(),
+            }),
+            MessageBody::Add { delta } => {
+                self.version += 1;
+                self.counter.add(
+                    &node.node_id,
                     CounterValue {
-                        msg_id: last_msg_id,
+                        version: self.version,
                         value: delta,
                     },
                 );
 
-                Some(MessageBody::AddOk {
-                    in_reply_to: msg_id,
-                })
+                Some(MessageBody::AddOk)
             }
-            MessageBody::Gossip { msg_id, counter } => {
-                let mut state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a Gossip message");
-
-                // Merge all node's values the comming data except by itself.
-                for (node_id, c) in counter
-                    .data
-                    .iter()
-                    .filter(|(node_id, _)| **node_id != self.node_id)
-                {
-                    state.counter.merge(node_id, c.clone());
-                }
+            MessageBody::Gossip { counter } => {
+                self.merge(ctx.log(), &node.node_id, counter);
 
                 Some(MessageBody::GossipOk {
-                    counter: state.counter.clone(),
-                    in_reply_to: msg_id,
+                    counter: self.counter.clone(),
                 })
             }
-            MessageBody::GossipOk {
-                in_reply_to: _,
-                counter,
-            } => {
-                let mut state = self
-                    .state
-                    .lock()
-                    .expect("State poisoned when replying to a Gossip message");
-
-                // Merge all node's values with the coming except by itself.
-                for (node_id, c) in counter
-                    .data
-                    .iter()
-                    .filter(|(node_id, _)| **node_id != self.node_id)
-                {
-                    state.counter.merge(node_id, c.clone());
-                }
-
-                None
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
             }
-            body => unimplemented!("Message {:?} not implemented yet", body),
         };
 
-        match body {
-            Some(b) => Ok(Some(Message {
-                src: self.node_id.clone(),
-                dest: req.src.clone(),
-                body: b,
-            })),
-            None => Ok(None),
-        }
-    }
-
-    pub fn write(&self, msg: Message) -> anyhow::Result<()> {
-        let json = serde_json::to_string(&msg).context("Message serialization error")?;
-
-        println!("{}", json);
-
-        Ok(())
+        Ok(body)
     }
 }
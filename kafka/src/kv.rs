@@ -0,0 +1,663 @@
+use maelstrom_core::{
+    Error, Message,
+    async_runtime::{Context, Handler},
+    debug,
+    services::{Kv, KvError},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use crate::{
+    log::{Entry, POLL_LIMIT, Retention, Trimmed},
+    node::MessageBody,
+};
+
+/// Most messages a segment of a log holds.
+const SEGMENT_LEN: u64 = POLL_LIMIT as u64;
+
+/// Messages of a segment, `None` for those dropped by the retention.
+type Segment = Vec<Option<i64>>;
+
+/// Kafka-style log kept in Maelstrom's `lin-kv`, so that every node can serve every key.
+///
+/// The log of a key is cut into segments of `SEGMENT_LEN` messages, each of them a value of its
+/// own, `log/{key}/{segment}`, so a poll reads at most two values per key. A send appends its
+/// message to the last segment with a compare-and-set, which gives it the offset at the same
+/// time: a send that fails or dies leaves no hole, and only one send wins each offset. A segment
+/// is only started once the previous one is full.
+///
+/// Retention works a segment at a time: the node a consumer commits through applies it to the
+/// segments every consumer has committed past the end of, which keeps their length, so offsets
+/// never move.
+#[derive(Debug)]
+pub struct KvKafka {
+    kv: Kv,
+    commits: Commits,
+    retention: Retention,
+    /// Segment of each key this node last appended to, where its sends start looking for room.
+    segments: Mutex<HashMap<String, u64>>,
+    /// Segments of each key, from the first one, that this node has applied the retention to.
+    retained: Mutex<HashMap<String, u64>>,
+}
+
+impl KvKafka {
+    fn new(retention: Retention) -> Self {
+        Self {
+            kv: Kv::lin(),
+            commits: Commits::default(),
+            retention,
+            segments: Mutex::default(),
+            retained: Mutex::default(),
+        }
+    }
+
+    /// Reads a segment of `key`, a segment that was never started being empty.
+    async fn segment(&self, ctx: &Context, key: &str, segment: u64) -> Result<Segment, KvError> {
+        match self.kv.read(ctx, format!("log/{}/{}", key, segment)).await {
+            Err(KvError::KeyDoesNotExist) => Ok(Vec::new()),
+            result => result,
+        }
+    }
+
+    /// Appends `msg` to the last segment of `key`, returning its offset.
+    async fn send(&self, ctx: &Context, key: &str, msg: i64) -> Result<u64, KvError> {
+        let mut segment = self
+            .segments
+            .lock()
+            .expect("Segments poisoned")
+            .get(key)
+            .copied()
+            .unwrap_or_default();
+
+        loop {
+            let msgs: Segment = match self.kv.read(ctx, format!("log/{}/{}", key, segment)).await {
+                Ok(msgs) => msgs,
+                Err(KvError::KeyDoesNotExist) => {
+                    // Starts the segment empty, which leaves it alone if another send did first.
+                    match self
+                        .kv
+                        .cas(
+                            ctx,
+                            format!("log/{}/{}", key, segment),
+                            Segment::new(),
+                            Segment::new(),
+                            true,
+                        )
+                        .await
+                    {
+                        Ok(()) | Err(KvError::PreconditionFailed) => continue,
+                        Err(error) => return Err(error),
+                    }
+                }
+                Err(error) => return Err(error),
+            };
+
+            if msgs.len() as u64 >= SEGMENT_LEN {
+                segment += 1;
+                continue;
+            }
+
+            let offset = segment * SEGMENT_LEN + msgs.len() as u64;
+            let mut appended = msgs.clone();
+            appended.push(Some(msg));
+
+            match self
+                .kv
+                .cas(
+                    ctx,
+                    format!("log/{}/{}", key, segment),
+                    msgs,
+                    appended,
+                    false,
+                )
+                .await
+            {
+                Ok(()) => {
+                    self.segments
+                        .lock()
+                        .expect("Segments poisoned")
+                        .insert(key.to_string(), segment);
+
+                    return Ok(offset);
+                }
+                Err(KvError::PreconditionFailed) => {
+                    debug!(
+                        ctx.log(),
+                        "Offset {} of {} was taken, retrying", offset, key
+                    );
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Reads up to `POLL_LIMIT` offsets of `key` from `offset` on, from the segment holding the
+    /// offset and the next one. Returns their messages, and the ranges trimmed among them.
+    async fn read_from(
+        &self,
+        ctx: &Context,
+        key: &str,
+        offset: u64,
+    ) -> Result<(Vec<Entry>, Vec<Trimmed>), KvError> {
+        let end = offset + POLL_LIMIT as u64;
+        let mut entries = Vec::new();
+        let mut trimmed: Vec<Trimmed> = Vec::new();
+        let mut segment = offset / SEGMENT_LEN;
+
+        while segment * SEGMENT_LEN < end {
+            let msgs = self.segment(ctx, key, segment).await?;
+
+            for (o, msg) in (segment * SEGMENT_LEN..end).zip(msgs.iter()) {
+                match msg {
+                    _ if o < offset => {}
+                    Some(msg) => entries.push((o, *msg)),
+                    None => match trimmed.last_mut() {
+                        Some((_, last)) if *last + 1 == o => *last = o,
+                        _ => trimmed.push((o, o)),
+                    },
+                }
+            }
+
+            // Later segments are only started once this one is full.
+            if (msgs.len() as u64) < SEGMENT_LEN {
+                break;
+            }
+
+            segment += 1;
+        }
+
+        Ok((entries, trimmed))
+    }
+
+    async fn poll(
+        &self,
+        ctx: &Context,
+        offsets: HashMap<String, u64>,
+    ) -> Result<(HashMap<String, Vec<Entry>>, HashMap<String, Vec<Trimmed>>), KvError> {
+        let mut msgs = HashMap::new();
+        let mut trimmed = HashMap::new();
+
+        for (key, offset) in offsets {
+            let (entries, ranges) = self.read_from(ctx, &key, offset).await?;
+
+            if !entries.is_empty() {
+                msgs.insert(key.clone(), entries);
+            }
+
+            if !ranges.is_empty() {
+                trimmed.insert(key, ranges);
+            }
+        }
+
+        Ok((msgs, trimmed))
+    }
+
+    /// Applies the retention to the segments of `key` below `below`, the offset every consumer
+    /// has committed, that this node has not done yet.
+    ///
+    /// Compaction looks for later copies among the messages sent so far: a segment is compacted
+    /// once, so a copy sent afterwards no longer drops its message.
+    async fn retain(&self, ctx: &Context, key: &str, below: u64) -> Result<(), KvError> {
+        let start = self
+            .retained
+            .lock()
+            .expect("Retention poisoned")
+            .get(key)
+            .copied()
+            .unwrap_or_default();
+        let end = below / SEGMENT_LEN;
+
+        if self.retention == Retention::Keep || start >= end {
+            return Ok(());
+        }
+
+        let mut later = HashSet::new();
+
+        if self.retention == Retention::Compact {
+            for segment in end.. {
+                let msgs = self.segment(ctx, key, segment).await?;
+                later.extend(msgs.iter().flatten().copied());
+
+                if (msgs.len() as u64) < SEGMENT_LEN {
+                    break;
+                }
+            }
+        }
+
+        for segment in (start..end).rev() {
+            later = self.retain_segment(ctx, key, segment, later).await?;
+        }
+
+        let mut retained = self.retained.lock().expect("Retention poisoned");
+        let retained = retained.entry(key.to_string()).or_default();
+        *retained = end.max(*retained);
+
+        Ok(())
+    }
+
+    /// Drops the messages of a segment every consumer has committed, `later` holding the
+    /// messages that follow it for compaction. Returns them along with the ones this segment
+    /// keeps.
+    async fn retain_segment(
+        &self,
+        ctx: &Context,
+        key: &str,
+        segment: u64,
+        later: HashSet<i64>,
+    ) -> Result<HashSet<i64>, KvError> {
+        loop {
+            let msgs = self.segment(ctx, key, segment).await?;
+            let mut kept = msgs.clone();
+            let mut seen = later.clone();
+
+            for msg in kept.iter_mut().rev() {
+                let dropped = match (self.retention, *msg) {
+                    (_, None) | (Retention::Keep, _) => false,
+                    (Retention::Delete, _) => true,
+                    (Retention::Compact, Some(msg)) => !seen.insert(msg),
+                };
+
+                if dropped {
+                    *msg = None;
+                }
+            }
+
+            if kept == msgs {
+                return Ok(seen);
+            }
+
+            match self
+                .kv
+                .cas(ctx, format!("log/{}/{}", key, segment), msgs, kept, false)
+                .await
+            {
+                Ok(()) => return Ok(seen),
+                // Another node applied the retention, or a send filled the segment.
+                Err(KvError::PreconditionFailed) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl Handler for KvKafka {
+    type Payload = MessageBody;
+
+    fn init(_ctx: &Context) -> anyhow::Result<Self> {
+        Ok(Self::new(Retention::from_env()?))
+    }
+
+    async fn handle(
+        &self,
+        ctx: Context,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let body = match req.body.payload {
+            MessageBody::Send { key, msg } => MessageBody::SendOk {
+                offset: self.send(&ctx, &key, msg).await.map_err(Error::from)?,
+            },
+            MessageBody::Poll { offsets } => {
+                let (msgs, trimmed) = self.poll(&ctx, offsets).await.map_err(Error::from)?;
+
+                MessageBody::PollOk { msgs, trimmed }
+            }
+            MessageBody::CommitOffsets { offsets } => {
+                let retained = self.commits.commit(&ctx, &req.src, offsets).await?;
+
+                for (key, below) in retained {
+                    // The offsets are committed either way, the next commit tries again.
+                    if let Err(error) = self.retain(&ctx, &key, below).await {
+                        debug!(ctx.log(), "Retention of {} failed: {}", key, error);
+                    }
+                }
+
+                MessageBody::CommitOffsetsOk
+            }
+            MessageBody::ListCommittedOffsets { keys } => MessageBody::ListCommittedOffsetsOk {
+                offsets: self.commits.list(&ctx, keys).await?,
+            },
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        };
+
+        Ok(Some(body))
+    }
+}
+
+/// Committed offsets kept in Maelstrom's `lin-kv`, in `commit/{key}`, by consumer. They are
+/// moved forward with compare-and-sets, so they never go back whichever node commits them.
+#[derive(Debug)]
+pub struct Commits {
+    kv: Kv,
+}
+
+impl Default for Commits {
+    fn default() -> Self {
+        Self { kv: Kv::lin() }
+    }
+}
+
+impl Commits {
+    async fn consumers(
+        &self,
+        ctx: &Context,
+        key: &str,
+    ) -> Result<Option<HashMap<String, u64>>, KvError> {
+        match self.kv.read(ctx, format!("commit/{}", key)).await {
+            Ok(consumers) => Ok(Some(consumers)),
+            Err(KvError::KeyDoesNotExist) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Commits `key` up to `offset` for `consumer`, and returns the lowest offset its consumers
+    /// have committed.
+    async fn commit_key(
+        &self,
+        ctx: &Context,
+        consumer: &str,
+        key: &str,
+        offset: u64,
+    ) -> Result<u64, KvError> {
+        loop {
+            let committed = self.consumers(ctx, key).await?;
+            let mut consumers = committed.clone().unwrap_or_default();
+
+            if consumers
+                .get(consumer)
+                .is_some_and(|committed| *committed >= offset)
+            {
+                return Ok(consumers.values().min().copied().unwrap_or_default());
+            }
+
+            consumers.insert(consumer.to_string(), offset);
+
+            match self
+                .kv
+                .cas(
+                    ctx,
+                    format!("commit/{}", key),
+                    committed,
+                    Some(consumers.clone()),
+                    true,
+                )
+                .await
+            {
+                Ok(()) => return Ok(consumers.values().min().copied().unwrap_or_default()),
+                Err(KvError::PreconditionFailed) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Commits the offsets of `consumer`, and returns for each of their keys the offset every
+    /// consumer of the key has committed, below which the retention applies.
+    pub async fn commit(
+        &self,
+        ctx: &Context,
+        consumer: &str,
+        offsets: HashMap<String, u64>,
+    ) -> Result<HashMap<String, u64>, Error> {
+        let mut retained = HashMap::new();
+
+        for (key, offset) in offsets {
+            let below = self.commit_key(ctx, consumer, &key, offset).await?;
+
+            retained.insert(key, below);
+        }
+
+        Ok(retained)
+    }
+
+    /// Returns the committed offset of every key of `keys` that has one, the highest among its
+    /// consumers.
+    pub async fn list(
+        &self,
+        ctx: &Context,
+        keys: Vec<String>,
+    ) -> Result<HashMap<String, u64>, Error> {
+        let mut offsets = HashMap::new();
+
+        for key in keys {
+            let committed = self.consumers(ctx, &key).await?;
+
+            if let Some(offset) = committed.and_then(|consumers| consumers.into_values().max()) {
+                offsets.insert(key, offset);
+            }
+        }
+
+        Ok(offsets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maelstrom_core::{
+        async_runtime::run_with,
+        harness::{Cluster, LinKv},
+    };
+    use std::thread;
+
+    use super::*;
+
+    fn start(node_count: usize) -> Cluster {
+        Cluster::builder(node_count)
+            .service(LinKv::new())
+            .start(run_with::<KvKafka, _, _>)
+            .unwrap()
+    }
+
+    #[test]
+    fn concurrent_sends_get_distinct_offsets() {
+        let cluster = start(3);
+
+        let mut sent: Vec<Entry> = thread::scope(|scope| {
+            let senders: Vec<_> = cluster
+                .node_ids()
+                .iter()
+                .enumerate()
+                .map(|(i, node_id)| {
+                    let cluster = &cluster;
+
+                    scope.spawn(move || {
+                        (0..10)
+                            .map(|n| {
+                                let msg = (i * 100 + n) as i64;
+                                let send = MessageBody::Send {
+                                    key: "k".to_string(),
+                                    msg,
+                                };
+
+                                match cluster.call(node_id, send).unwrap() {
+                                    MessageBody::SendOk { offset } => (offset, msg),
+                                    reply => panic!("Unexpected reply {:?}", reply),
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            senders
+                .into_iter()
+                .flat_map(|sender| sender.join().unwrap())
+                .collect()
+        });
+        sent.sort();
+
+        let offsets: Vec<u64> = sent.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, (0..30).collect::<Vec<_>>());
+
+        // Any node serves the whole log, in offset order.
+        let poll = MessageBody::Poll {
+            offsets: HashMap::from([("k".to_string(), 0)]),
+        };
+        match cluster.call("n2", poll).unwrap() {
+            MessageBody::PollOk { msgs, .. } => assert_eq!(msgs["k"], sent),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+
+        cluster.stop().unwrap();
+    }
+
+    #[test]
+    fn polls_from_an_offset_and_shares_commits() {
+        let cluster = start(2);
+
+        for msg in 0..5 {
+            let send = MessageBody::Send {
+                key: "k".to_string(),
+                msg,
+            };
+            let _: MessageBody = cluster.call("n0", send).unwrap();
+        }
+
+        let poll = MessageBody::Poll {
+            offsets: HashMap::from([("k".to_string(), 3), ("other".to_string(), 0)]),
+        };
+        match cluster.call("n1", poll).unwrap() {
+            MessageBody::PollOk { msgs, .. } => {
+                assert_eq!(
+                    msgs,
+                    HashMap::from([("k".to_string(), vec![(3, 3), (4, 4)])])
+                )
+            }
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+
+        let commit = MessageBody::CommitOffsets {
+            offsets: HashMap::from([("k".to_string(), 3)]),
+        };
+        let _: MessageBody = cluster.call("n0", commit).unwrap();
+
+        // An older commit does not move the offset back.
+        let commit = MessageBody::CommitOffsets {
+            offsets: HashMap::from([("k".to_string(), 1)]),
+        };
+        let _: MessageBody = cluster.call("n1", commit).unwrap();
+
+        let list = MessageBody::ListCommittedOffsets {
+            keys: vec!["k".to_string(), "other".to_string()],
+        };
+        match cluster.call("n1", list).unwrap() {
+            MessageBody::ListCommittedOffsetsOk { offsets } => {
+                assert_eq!(offsets, HashMap::from([("k".to_string(), 3)]))
+            }
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+
+        cluster.stop().unwrap();
+    }
+
+    #[test]
+    fn polls_read_across_segments() {
+        let cluster = start(2);
+        let count = SEGMENT_LEN + 5;
+
+        for msg in 0..count as i64 {
+            let send = MessageBody::Send {
+                key: "k".to_string(),
+                msg,
+            };
+            let node_id = &cluster.node_ids()[msg as usize % 2];
+
+            match cluster.call(node_id, send).unwrap() {
+                MessageBody::SendOk { offset } => assert_eq!(offset, msg as u64),
+                reply => panic!("Unexpected reply {:?}", reply),
+            }
+        }
+
+        for (offset, expected) in [
+            (0, 0..SEGMENT_LEN),
+            (SEGMENT_LEN - 2, SEGMENT_LEN - 2..count),
+        ] {
+            let poll = MessageBody::Poll {
+                offsets: HashMap::from([("k".to_string(), offset)]),
+            };
+
+            match cluster.call("n1", poll).unwrap() {
+                MessageBody::PollOk { msgs, .. } => assert_eq!(
+                    msgs["k"],
+                    expected.map(|o| (o, o as i64)).collect::<Vec<_>>()
+                ),
+                reply => panic!("Unexpected reply {:?}", reply),
+            }
+        }
+
+        cluster.stop().unwrap();
+    }
+
+    /// Compacts the committed messages.
+    struct Compacting(KvKafka);
+
+    impl Handler for Compacting {
+        type Payload = MessageBody;
+
+        fn init(_ctx: &Context) -> anyhow::Result<Self> {
+            Ok(Self(KvKafka::new(Retention::Compact)))
+        }
+
+        async fn handle(
+            &self,
+            ctx: Context,
+            req: Message<MessageBody>,
+        ) -> anyhow::Result<Option<MessageBody>> {
+            self.0.handle(ctx, req).await
+        }
+    }
+
+    #[test]
+    fn compaction_drops_committed_messages_sent_again() {
+        let cluster = Cluster::builder(2)
+            .service(LinKv::new())
+            .start(run_with::<Compacting, _, _>)
+            .unwrap();
+
+        // The second segment starts with copies of the first ten messages.
+        for offset in 0..SEGMENT_LEN + 10 {
+            let send = MessageBody::Send {
+                key: "k".to_string(),
+                msg: (offset % SEGMENT_LEN) as i64,
+            };
+            let _: MessageBody = cluster.call("n0", send).unwrap();
+        }
+
+        let commit = MessageBody::CommitOffsets {
+            offsets: HashMap::from([("k".to_string(), SEGMENT_LEN + 1)]),
+        };
+        let _: MessageBody = cluster.call("n1", commit).unwrap();
+
+        let poll = MessageBody::Poll {
+            offsets: HashMap::from([("k".to_string(), 0)]),
+        };
+        match cluster.call("n0", poll).unwrap() {
+            MessageBody::PollOk { msgs, trimmed } => {
+                assert_eq!(
+                    msgs["k"],
+                    (10..SEGMENT_LEN).map(|o| (o, o as i64)).collect::<Vec<_>>()
+                );
+                assert_eq!(trimmed["k"], [(0, 9)]);
+            }
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+
+        // The second segment is not committed past its end, so it keeps every message.
+        let poll = MessageBody::Poll {
+            offsets: HashMap::from([("k".to_string(), SEGMENT_LEN)]),
+        };
+        match cluster.call("n0", poll).unwrap() {
+            MessageBody::PollOk { msgs, trimmed } => {
+                assert_eq!(msgs["k"].len(), 10);
+                assert!(trimmed.is_empty());
+            }
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+
+        cluster.stop().unwrap();
+    }
+}
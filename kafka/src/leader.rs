@@ -0,0 +1,928 @@
+use anyhow::Context as _;
+use maelstrom_core::{
+    Error, ErrorCode, Message, Node, RpcOptions,
+    async_runtime::{Context, Handler},
+    debug, info,
+};
+use std::{
+    collections::{HashMap, HashSet, hash_map},
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    kv::Commits,
+    log::{Entry, Logs, POLL_LIMIT, Retention},
+    node::MessageBody,
+};
+
+/// Environment variable holding how many nodes keep the log of a key, 1 by default.
+const REPLICATION_FACTOR_ENV: &str = "KAFKA_REPLICATION_FACTOR";
+
+/// Environment variable holding how many in-sync replicas, the leader included, must have a
+/// message before its send is acknowledged. A majority of the replication factor by default.
+const MIN_ISR_ENV: &str = "KAFKA_MIN_ISR";
+
+const REPLICATE_INTERVAL: Duration = Duration::from_millis(100);
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a peer can stay silent before it is considered down.
+const FAILURE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy)]
+struct Replication {
+    factor: usize,
+    min_isr: usize,
+}
+
+impl Replication {
+    fn from_env(node_count: usize) -> anyhow::Result<Self> {
+        let factor = env_usize(REPLICATION_FACTOR_ENV)?
+            .unwrap_or(1)
+            .clamp(1, node_count);
+        let min_isr = env_usize(MIN_ISR_ENV)?
+            .unwrap_or(factor / 2 + 1)
+            .clamp(1, factor);
+
+        Ok(Self { factor, min_isr })
+    }
+}
+
+fn env_usize(name: &str) -> anyhow::Result<Option<usize>> {
+    env::var(name)
+        .ok()
+        .map(|value| value.parse().with_context(|| format!("Invalid {}", name)))
+        .transpose()
+}
+
+/// Kafka-style log where every key is led by a single node.
+///
+/// The log of a key is kept by `KAFKA_REPLICATION_FACTOR` replicas: its owner, given by
+/// `Node::owner`, and the nodes that follow it in `node_ids`. The leader of the key is the first
+/// of its replicas that is still up, as far as heartbeats tell. It is the only node that appends
+/// to the log, so it hands out offsets without any coordination, and other nodes forward the
+/// sends of the key to it.
+///
+/// A send is acknowledged once every in-sync replica has the message, and only if they are at
+/// least `KAFKA_MIN_ISR`. A follower leaves the in-sync replicas when it misses a message, and
+/// joins them back once the batched replication has caught it up. Batches also carry every
+/// message to the nodes that are not replicas of the key, which serve polls from their copy.
+/// Committed offsets are kept in `lin-kv`, and every node applies the retention to its copy of
+/// the logs once the consumers have committed through any of them.
+///
+/// A node that takes the lead of a key, or takes it back after being cut off, first fetches the
+/// messages the other replicas got from the leaders in between, and only appends after them.
+/// Followers never replace a message they have, they reject the batches that would, and the
+/// leader keeps sending those until it has caught up.
+///
+/// Leadership is only decided from local heartbeats, without consensus, so this approximates
+/// Kafka rather than reproduces it: an acknowledged message survives the loss of its leader as
+/// long as a replica that has it is up when the next leader catches up, and the messages a
+/// deposed leader appended without acknowledging them are replaced by those of the replicas.
+#[derive(Debug)]
+pub struct LeaderKafka {
+    state: Arc<State>,
+    commits: Commits,
+    retention: Retention,
+}
+
+/// Whether a node can append to a key it leads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Leading {
+    /// The node is fetching the messages the other replicas got from former leaders.
+    CatchingUp,
+    /// The node has the messages of the other replicas, and appends after them.
+    CaughtUp,
+}
+
+#[derive(Debug)]
+struct State {
+    replication: Replication,
+    logs: Mutex<Logs>,
+    /// Messages appended by this node that a peer has not acknowledged yet, by peer and key.
+    unreplicated: Mutex<HashMap<String, HashMap<String, Vec<Entry>>>>,
+    /// When each peer was last heard from.
+    last_seen: Mutex<HashMap<String, Instant>>,
+    /// Followers that missed a message and have not been caught up since.
+    out_of_sync: Mutex<HashSet<String>>,
+    /// Keys this node leads, by how far it is from appending to them.
+    leading: Mutex<HashMap<String, Leading>>,
+}
+
+impl State {
+    fn new(node: &Node, replication: Replication, retention: Retention) -> Self {
+        // Every peer is given the benefit of the doubt until it has been silent for too long.
+        let now = Instant::now();
+
+        Self {
+            replication,
+            logs: Mutex::new(Logs::new(retention)),
+            unreplicated: Mutex::default(),
+            last_seen: Mutex::new(node.peers().map(|peer| (peer.clone(), now)).collect()),
+            out_of_sync: Mutex::default(),
+            leading: Mutex::default(),
+        }
+    }
+
+    fn touch(&self, node: &Node, peer: &str) {
+        let came_back = match self
+            .last_seen
+            .lock()
+            .expect("Liveness poisoned")
+            .get_mut(peer)
+        {
+            Some(last_seen) => {
+                let came_back = last_seen.elapsed() >= FAILURE_TIMEOUT;
+                *last_seen = Instant::now();
+
+                came_back
+            }
+            None => false,
+        };
+
+        // A peer that was down for this node may have led the keys it replicates in the
+        // meantime, the others are left alone.
+        if came_back {
+            self.leading
+                .lock()
+                .expect("Leadership poisoned")
+                .retain(|key, _| {
+                    !self
+                        .replicas(node, key)
+                        .iter()
+                        .any(|replica| *replica == peer)
+                });
+        }
+    }
+
+    fn is_alive(&self, node: &Node, peer: &str) -> bool {
+        peer == node.node_id
+            || self
+                .last_seen
+                .lock()
+                .expect("Liveness poisoned")
+                .get(peer)
+                .is_some_and(|last_seen| last_seen.elapsed() < FAILURE_TIMEOUT)
+    }
+
+    /// Nodes that keep the log of `key`, in the order in which they lead it: its owner and the
+    /// nodes that follow it on the ring of `node_ids`.
+    fn replicas<'a>(&self, node: &'a Node, key: &str) -> Vec<&'a String> {
+        let owner = node.owner(key);
+        let start = node.node_ids.iter().position(|id| id == owner).unwrap_or(0);
+
+        (0..self.replication.factor)
+            .map(|i| &node.node_ids[(start + i) % node.node_ids.len()])
+            .collect()
+    }
+
+    fn leader<'a>(&self, node: &'a Node, key: &str) -> &'a str {
+        let replicas = self.replicas(node, key);
+
+        replicas
+            .iter()
+            .find(|replica| self.is_alive(node, replica))
+            .unwrap_or(&replicas[0])
+    }
+
+    /// Followers of `key` that are up and have every message of the leader.
+    fn in_sync<'a>(&self, node: &'a Node, key: &str) -> Vec<&'a String> {
+        let out_of_sync = self.out_of_sync.lock().expect("ISR poisoned").clone();
+
+        self.replicas(node, key)
+            .into_iter()
+            .filter(|replica| **replica != node.node_id)
+            .filter(|replica| self.is_alive(node, replica) && !out_of_sync.contains(*replica))
+            .collect()
+    }
+
+    fn append(&self, ctx: &Context, key: &str, msg: i64) -> u64 {
+        let offset = self.logs.lock().expect("Logs poisoned").append(key, msg);
+
+        self.enqueue(ctx, key, &[(offset, msg)]);
+
+        offset
+    }
+
+    /// Forgets the messages `peer` has acknowledged.
+    fn replicated(&self, peer: &str, key: &str, acknowledged: &[Entry]) {
+        let mut unreplicated = self.unreplicated.lock().expect("Replication poisoned");
+
+        if let Some(pending) = unreplicated.get_mut(peer)
+            && let Some(entries) = pending.get_mut(key)
+        {
+            entries.retain(|entry| !acknowledged.contains(entry));
+
+            if entries.is_empty() {
+                pending.remove(key);
+            }
+        }
+    }
+
+    /// Queues messages of `key` for every peer.
+    fn enqueue(&self, ctx: &Context, key: &str, entries: &[Entry]) {
+        let mut unreplicated = self.unreplicated.lock().expect("Replication poisoned");
+
+        for peer in ctx.node().peers() {
+            unreplicated
+                .entry(peer.clone())
+                .or_default()
+                .entry(key.to_string())
+                .or_default()
+                .extend_from_slice(entries);
+        }
+    }
+
+    /// Forgets the messages of `key` this node has not replicated yet, to every peer.
+    fn dequeue(&self, key: &str, dropped: impl Fn(&Entry) -> bool) {
+        let mut unreplicated = self.unreplicated.lock().expect("Replication poisoned");
+
+        for pending in unreplicated.values_mut() {
+            if let Some(entries) = pending.get_mut(key) {
+                entries.retain(|entry| !dropped(entry));
+
+                if entries.is_empty() {
+                    pending.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Makes sure this node has the messages of `key` the other replicas got from former
+    /// leaders before it appends to the key.
+    async fn lead(&self, ctx: &Context, key: &str) -> Result<(), Error> {
+        match self
+            .leading
+            .lock()
+            .expect("Leadership poisoned")
+            .entry(key.to_string())
+        {
+            hash_map::Entry::Occupied(leading) => {
+                return match leading.get() {
+                    Leading::CaughtUp => Ok(()),
+                    Leading::CatchingUp => Err(Error::new(
+                        ErrorCode::TemporarilyUnavailable,
+                        format!("Catching up on {}", key),
+                    )),
+                };
+            }
+            hash_map::Entry::Vacant(leading) => {
+                leading.insert(Leading::CatchingUp);
+            }
+        }
+
+        let result = self.catch_up(ctx, key).await;
+        let mut leading = self.leading.lock().expect("Leadership poisoned");
+
+        // A peer coming back during the catch up may have led the key too, and was not fetched.
+        if leading.get(key) != Some(&Leading::CatchingUp) {
+            return Err(Error::new(
+                ErrorCode::TemporarilyUnavailable,
+                format!("Leadership of {} changed while catching up", key),
+            ));
+        }
+
+        match result {
+            Ok(()) => {
+                leading.insert(key.to_string(), Leading::CaughtUp);
+
+                Ok(())
+            }
+            Err(error) => {
+                leading.remove(key);
+
+                Err(error)
+            }
+        }
+    }
+
+    /// Fetches the messages of `key` from the other replicas that are up, and adopts them.
+    async fn catch_up(&self, ctx: &Context, key: &str) -> Result<(), Error> {
+        let node = ctx.node();
+
+        // The peers have acknowledged every message before the first one still queued for them,
+        // so the replicas can only differ from there.
+        let offset = self
+            .unreplicated
+            .lock()
+            .expect("Replication poisoned")
+            .values()
+            .filter_map(|pending| pending.get(key)?.iter().map(|(offset, _)| *offset).min())
+            .min()
+            .unwrap_or_else(|| self.logs.lock().expect("Logs poisoned").next_offset(key));
+
+        let mut fetched = Vec::new();
+
+        for replica in self.replicas(node, key) {
+            if *replica == node.node_id || !self.is_alive(node, replica) {
+                continue;
+            }
+
+            let options = RpcOptions {
+                timeout: FAILURE_TIMEOUT,
+                retries: 0,
+            };
+            let fetch = MessageBody::Fetch {
+                key: key.to_string(),
+                offset,
+            };
+
+            match ctx
+                .rpc::<_, MessageBody>(replica, fetch, options)
+                .await
+                .map(|reply| reply.body.payload)
+            {
+                Ok(MessageBody::FetchOk { entries }) => fetched.push(entries),
+                Ok(payload) => {
+                    return Err(Error::new(
+                        ErrorCode::Crash,
+                        format!("Unexpected reply {:?}", payload),
+                    ));
+                }
+                Err(error) => {
+                    return Err(Error::new(
+                        ErrorCode::TemporarilyUnavailable,
+                        format!("Could not catch up on {} from {}: {}", key, replica, error),
+                    ));
+                }
+            }
+        }
+
+        // Replicas can only disagree on messages nobody acknowledged, the longest log is the one
+        // that went on the longest without its leader being deposed, so it is adopted last.
+        fetched.sort_by_key(|entries| entries.last().map(|(offset, _)| *offset));
+
+        for entries in fetched {
+            if entries.is_empty() {
+                continue;
+            }
+
+            let replaced = self
+                .logs
+                .lock()
+                .expect("Logs poisoned")
+                .adopt(key, &entries);
+
+            if !replaced.is_empty() {
+                info!(
+                    ctx.log(),
+                    "Catching up on {} replaced {} messages",
+                    key,
+                    replaced.len()
+                );
+                self.dequeue(key, |entry| replaced.contains(entry));
+            }
+
+            // Peers that already have them accept them again, the others need them.
+            self.enqueue(ctx, key, &entries);
+        }
+
+        Ok(())
+    }
+
+    /// Stops appending to `key`, which another node leads.
+    fn resign(&self, key: &str) {
+        self.leading
+            .lock()
+            .expect("Leadership poisoned")
+            .remove(key);
+    }
+
+    /// Appends a message as the leader of `key`, waiting for the in-sync replicas to have it.
+    async fn send(&self, ctx: &Context, key: &str, msg: i64) -> Result<u64, Error> {
+        let node = ctx.node();
+        let followers = self.in_sync(node, key);
+
+        if followers.len() + 1 < self.replication.min_isr {
+            return Err(Error::new(
+                ErrorCode::TemporarilyUnavailable,
+                format!(
+                    "Only {} in-sync replicas of {}, {} needed",
+                    followers.len() + 1,
+                    key,
+                    self.replication.min_isr
+                ),
+            ));
+        }
+
+        self.lead(ctx, key).await?;
+
+        let offset = self.append(ctx, key, msg);
+        let entry = (offset, msg);
+
+        for follower in followers {
+            let options = RpcOptions {
+                timeout: FAILURE_TIMEOUT,
+                retries: 0,
+            };
+            let append = MessageBody::Append {
+                key: key.to_string(),
+                entry,
+            };
+
+            match ctx.rpc::<_, MessageBody>(follower, append, options).await {
+                Ok(_) => self.replicated(follower, key, &[entry]),
+                Err(error) => {
+                    info!(
+                        ctx.log(),
+                        "{} leaves the in-sync replicas: {}", follower, error
+                    );
+
+                    self.out_of_sync
+                        .lock()
+                        .expect("ISR poisoned")
+                        .insert(follower.clone());
+
+                    // The message stays in the log and reaches the follower with the next
+                    // batches, so the send may still have happened.
+                    return Err(Error::new(
+                        ErrorCode::Crash,
+                        format!("{} did not get offset {} of {}", follower, offset, key),
+                    ));
+                }
+            }
+        }
+
+        Ok(offset)
+    }
+
+    /// Sends the messages `peer` has not acknowledged yet, in a single batch.
+    async fn replicate(&self, ctx: &Context, peer: &str) -> anyhow::Result<()> {
+        let msgs = self
+            .unreplicated
+            .lock()
+            .expect("Replication poisoned")
+            .get(peer)
+            .cloned()
+            .unwrap_or_default();
+
+        if msgs.is_empty() {
+            // A follower that has every message is in sync again.
+            if self.out_of_sync.lock().expect("ISR poisoned").remove(peer) {
+                info!(ctx.log(), "{} joins the in-sync replicas", peer);
+            }
+
+            return Ok(());
+        }
+
+        // A batch that times out is not retried, the next tick sends it again with whatever was
+        // appended in the meantime.
+        let options = RpcOptions {
+            timeout: REPLICATE_INTERVAL,
+            retries: 0,
+        };
+
+        let reply = ctx
+            .rpc::<_, MessageBody>(peer, MessageBody::Replicate { msgs: msgs.clone() }, options)
+            .await;
+
+        let rejected = match reply {
+            Ok(reply) => match reply.body.payload {
+                MessageBody::ReplicateOk { rejected } => rejected,
+                payload => {
+                    debug!(ctx.log(), "Unexpected reply of {}: {:?}", peer, payload);
+
+                    return Ok(());
+                }
+            },
+            Err(error) => {
+                debug!(ctx.log(), "Replication to {} failed: {}", peer, error);
+
+                return Ok(());
+            }
+        };
+
+        if !rejected.is_empty() {
+            debug!(
+                ctx.log(),
+                "{} rejected the messages of {:?}", peer, rejected
+            );
+        }
+
+        // Rejected messages stay queued, until the peer takes them or this node stops leading.
+        for (key, sent) in msgs.iter().filter(|(key, _)| !rejected.contains(key)) {
+            self.replicated(peer, key, sent);
+        }
+
+        Ok(())
+    }
+
+    /// Stores messages of `key` sent by `src`, if it leads the key as far as this node knows and
+    /// none of them replaces a message this node has.
+    fn follow(&self, node: &Node, src: &str, key: &str, entries: &[Entry]) -> bool {
+        if self.leader(node, key) != src {
+            return false;
+        }
+
+        // The messages this node appended while it led the key were fetched by `src` when it
+        // took over, and are its to replicate now.
+        self.resign(key);
+        self.dequeue(key, |_| true);
+
+        self.logs.lock().expect("Logs poisoned").put(key, entries)
+    }
+}
+
+impl LeaderKafka {
+    /// Starts the replication and heartbeat tasks of a node keeping `replication` copies of
+    /// every log, whose committed messages are dropped as `retention` says.
+    fn start(ctx: &Context, replication: Replication, retention: Retention) -> Self {
+        let node = ctx.node();
+        let state = Arc::new(State::new(node, replication, retention));
+
+        // A task per peer, so that a slow peer does not hold back the others.
+        for peer in node.peers() {
+            let state = state.clone();
+            let peer = peer.clone();
+
+            ctx.every("replicate", REPLICATE_INTERVAL, move |ctx| {
+                let state = state.clone();
+                let peer = peer.clone();
+
+                async move { state.replicate(&ctx, &peer).await }
+            });
+        }
+
+        if replication.factor > 1 {
+            ctx.every("heartbeat", HEARTBEAT_INTERVAL, |ctx| async move {
+                for peer in ctx.node().peers() {
+                    ctx.send(peer, MessageBody::Heartbeat)?;
+                }
+
+                Ok(())
+            });
+        }
+
+        Self {
+            state,
+            commits: Commits::default(),
+            retention,
+        }
+    }
+}
+
+impl Handler for LeaderKafka {
+    type Payload = MessageBody;
+
+    fn init(ctx: &Context) -> anyhow::Result<Self> {
+        let replication = Replication::from_env(ctx.node().node_ids.len())?;
+
+        Ok(Self::start(ctx, replication, Retention::from_env()?))
+    }
+
+    async fn handle(
+        &self,
+        ctx: Context,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let node = ctx.node();
+        let from_node = node.node_ids.contains(&req.src);
+
+        // Any message of a peer tells that it is up, not only heartbeats.
+        if from_node {
+            self.state.touch(node, &req.src);
+        }
+
+        let body = match req.body.payload {
+            MessageBody::Send { key, msg } => {
+                let leader = self.state.leader(node, &key);
+
+                if leader == node.node_id {
+                    MessageBody::SendOk {
+                        offset: self.state.send(&ctx, &key, msg).await?,
+                    }
+                } else if from_node {
+                    self.state.resign(&key);
+
+                    // Sends from other nodes are never forwarded, only answered: the sender
+                    // already picked this node as the leader of the key.
+                    return Err(Error::new(
+                        ErrorCode::TemporarilyUnavailable,
+                        format!("{} is led by {}", key, leader),
+                    )
+                    .into());
+                } else {
+                    self.state.resign(&key);
+
+                    ctx.call(leader, MessageBody::Send { key, msg }).await?
+                }
+            }
+            MessageBody::Poll { offsets } => {
+                let (msgs, trimmed) = self
+                    .state
+                    .logs
+                    .lock()
+                    .expect("Logs poisoned")
+                    .poll(&offsets, POLL_LIMIT);
+
+                MessageBody::PollOk { msgs, trimmed }
+            }
+            MessageBody::Append { key, entry } => {
+                if !self.state.follow(node, &req.src, &key, &[entry]) {
+                    return Err(Error::new(
+                        ErrorCode::TemporarilyUnavailable,
+                        format!(
+                            "{} is not the leader of {}, or offset {} is taken",
+                            req.src, key, entry.0
+                        ),
+                    )
+                    .into());
+                }
+
+                MessageBody::AppendOk
+            }
+            MessageBody::Replicate { msgs } => MessageBody::ReplicateOk {
+                rejected: msgs
+                    .iter()
+                    .filter(|(key, entries)| !self.state.follow(node, &req.src, key, entries))
+                    .map(|(key, _)| key.clone())
+                    .collect(),
+            },
+            MessageBody::Fetch { key, offset } => MessageBody::FetchOk {
+                entries: self
+                    .state
+                    .logs
+                    .lock()
+                    .expect("Logs poisoned")
+                    .entries_from(&key, offset),
+            },
+            MessageBody::Heartbeat => return Ok(None),
+            MessageBody::Trim { offsets } => {
+                self.state
+                    .logs
+                    .lock()
+                    .expect("Logs poisoned")
+                    .trim(&offsets);
+
+                return Ok(None);
+            }
+            MessageBody::CommitOffsets { offsets } => {
+                let retained = self.commits.commit(&ctx, &req.src, offsets).await?;
+
+                if self.retention != Retention::Keep {
+                    self.state
+                        .logs
+                        .lock()
+                        .expect("Logs poisoned")
+                        .trim(&retained);
+
+                    // A peer that misses it trims with the next commit of the keys.
+                    for peer in node.peers() {
+                        let trim = MessageBody::Trim {
+                            offsets: retained.clone(),
+                        };
+
+                        ctx.send(peer, trim)?;
+                    }
+                }
+
+                MessageBody::CommitOffsetsOk
+            }
+            MessageBody::ListCommittedOffsets { keys } => MessageBody::ListCommittedOffsetsOk {
+                offsets: self.commits.list(&ctx, keys).await?,
+            },
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        };
+
+        Ok(Some(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maelstrom_core::{
+        async_runtime::run_with,
+        harness::{Cluster, LinKv},
+    };
+    use std::thread;
+
+    use super::*;
+
+    /// Three replicas of every key, two of which must have a message before it is acknowledged.
+    struct ThreeReplicas(LeaderKafka);
+
+    impl Handler for ThreeReplicas {
+        type Payload = MessageBody;
+
+        fn init(ctx: &Context) -> anyhow::Result<Self> {
+            let replication = Replication {
+                factor: 3,
+                min_isr: 2,
+            };
+
+            Ok(Self(LeaderKafka::start(ctx, replication, Retention::Keep)))
+        }
+
+        async fn handle(
+            &self,
+            ctx: Context,
+            req: Message<MessageBody>,
+        ) -> anyhow::Result<Option<MessageBody>> {
+            self.0.handle(ctx, req).await
+        }
+    }
+
+    /// A single replica of every key, whose committed messages are deleted.
+    struct Deleting(LeaderKafka);
+
+    impl Handler for Deleting {
+        type Payload = MessageBody;
+
+        fn init(ctx: &Context) -> anyhow::Result<Self> {
+            let replication = Replication {
+                factor: 1,
+                min_isr: 1,
+            };
+
+            Ok(Self(LeaderKafka::start(
+                ctx,
+                replication,
+                Retention::Delete,
+            )))
+        }
+
+        async fn handle(
+            &self,
+            ctx: Context,
+            req: Message<MessageBody>,
+        ) -> anyhow::Result<Option<MessageBody>> {
+            self.0.handle(ctx, req).await
+        }
+    }
+
+    /// Waits for `condition` to hold, for a few seconds at most.
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn send(cluster: &Cluster, node_id: &str, key: &str, msg: i64) -> Result<u64, Error> {
+        let send = MessageBody::Send {
+            key: key.to_string(),
+            msg,
+        };
+
+        match cluster.call(node_id, send)? {
+            MessageBody::SendOk { offset } => Ok(offset),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    fn poll(cluster: &Cluster, node_id: &str, key: &str) -> Vec<Entry> {
+        let poll = MessageBody::Poll {
+            offsets: HashMap::from([(key.to_string(), 0)]),
+        };
+
+        match cluster.call(node_id, poll).unwrap() {
+            MessageBody::PollOk { mut msgs, .. } => msgs.remove(key).unwrap_or_default(),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn acknowledged_sends_survive_a_healed_partition() {
+        let cluster = Cluster::builder(3)
+            .service(LinKv::new())
+            .start(run_with::<ThreeReplicas, _, _>)
+            .unwrap();
+        let node = Node {
+            node_id: "n0".to_string(),
+            node_ids: cluster.node_ids().to_vec(),
+            index: 0,
+        };
+        let mut keys = (0..)
+            .map(|i| format!("k{}", i))
+            .filter(|key| node.owner(key) == "n0");
+        let (key, probe) = (keys.next().unwrap(), keys.next().unwrap());
+
+        let mut acknowledged = Vec::new();
+        for msg in 0..3 {
+            acknowledged.push((send(&cluster, "n0", &key, msg).unwrap(), msg));
+        }
+
+        // n1 takes over once n0 has been silent for long enough. Until then, it forwards the
+        // sends to n0, which never get there.
+        cluster.partition(&[&["n0"], &["n1", "n2"]]);
+
+        let mut offset = None;
+        wait_for(|| {
+            offset = send(&cluster, "n1", &key, 3).ok();
+            offset.is_some()
+        });
+        acknowledged.push((offset.unwrap(), 3));
+
+        for msg in 4..6 {
+            acknowledged.push((send(&cluster, "n1", &key, msg).unwrap(), msg));
+        }
+
+        // n0 lacks in-sync replicas to acknowledge anything.
+        assert!(send(&cluster, "n0", &key, 100).is_err());
+
+        // n0 leads again once the others hear from it, which a message of another of its keys
+        // reaching them tells.
+        cluster.heal();
+        wait_for(|| send(&cluster, "n0", &probe, 0).is_ok());
+        wait_for(|| {
+            cluster
+                .node_ids()
+                .iter()
+                .all(|node_id| !poll(&cluster, node_id, &probe).is_empty())
+        });
+
+        // It appends after what n1 acknowledged in the meantime.
+        let offset = send(&cluster, "n0", &key, 6).unwrap();
+        assert_eq!(offset, 6);
+        acknowledged.push((offset, 6));
+
+        for node_id in cluster.node_ids() {
+            wait_for(|| poll(&cluster, node_id, &key) == acknowledged);
+        }
+
+        cluster.stop().unwrap();
+    }
+
+    #[test]
+    fn every_node_deletes_committed_messages() {
+        let cluster = Cluster::builder(2)
+            .service(LinKv::new())
+            .start(run_with::<Deleting, _, _>)
+            .unwrap();
+
+        for msg in 0..3 {
+            send(&cluster, "n0", "k", msg).unwrap();
+        }
+        wait_for(|| poll(&cluster, "n1", "k").len() == 3);
+
+        let commit = MessageBody::CommitOffsets {
+            offsets: HashMap::from([("k".to_string(), 2)]),
+        };
+        let _: MessageBody = cluster.call("n1", commit).unwrap();
+
+        let from_start = MessageBody::Poll {
+            offsets: HashMap::from([("k".to_string(), 0)]),
+        };
+        for node_id in cluster.node_ids() {
+            wait_for(
+                || match cluster.call(node_id, from_start.clone()).unwrap() {
+                    MessageBody::PollOk { msgs, trimmed } => {
+                        msgs["k"] == [(2, 2)] && trimmed.get("k").is_some_and(|t| t == &[(0, 1)])
+                    }
+                    reply => panic!("Unexpected reply {:?}", reply),
+                },
+            );
+        }
+
+        cluster.stop().unwrap();
+    }
+
+    #[test]
+    fn a_peer_coming_back_only_resets_the_keys_it_replicates() {
+        let node = Node {
+            node_id: "n0".to_string(),
+            node_ids: vec!["n0".to_string(), "n1".to_string(), "n2".to_string()],
+            index: 0,
+        };
+        let replication = Replication {
+            factor: 2,
+            min_isr: 1,
+        };
+        let state = State::new(&node, replication, Retention::Keep);
+
+        // Keys of n0 are replicated on n1, keys of n2 on n0.
+        let key_of = |owner: &str| {
+            (0..)
+                .map(|i| format!("k{}", i))
+                .find(|key| node.owner(key) == owner)
+                .unwrap()
+        };
+        let (shared, other) = (key_of("n0"), key_of("n2"));
+
+        for key in [&shared, &other] {
+            state
+                .leading
+                .lock()
+                .unwrap()
+                .insert(key.clone(), Leading::CaughtUp);
+        }
+
+        state
+            .last_seen
+            .lock()
+            .unwrap()
+            .insert("n1".to_string(), Instant::now() - FAILURE_TIMEOUT * 2);
+        state.touch(&node, "n1");
+
+        let leading = state.leading.lock().unwrap();
+        assert_eq!(leading.keys().collect::<Vec<_>>(), [&other]);
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+};
+
+/// Most messages returned for a key by a single poll.
+pub const POLL_LIMIT: usize = 100;
+
+/// Environment variable selecting what happens to the messages every consumer of a key has
+/// committed: `keep` (default) keeps them, `delete` drops them, and `compact` only drops the
+/// ones whose message appears again later in the log, like Kafka keeps the latest record of each
+/// record key.
+pub const RETENTION_ENV: &str = "KAFKA_RETENTION";
+
+/// A message of a log, along with its offset.
+pub type Entry = (u64, i64);
+
+/// First and last offsets of messages dropped by the retention of a log.
+pub type Trimmed = (u64, u64);
+
+/// What happens to the messages every consumer of a key has committed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    #[default]
+    Keep,
+    Delete,
+    Compact,
+}
+
+impl Retention {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var(RETENTION_ENV).as_deref() {
+            Err(_) | Ok("keep") => Ok(Retention::Keep),
+            Ok("delete") => Ok(Retention::Delete),
+            Ok("compact") => Ok(Retention::Compact),
+            Ok(retention) => anyhow::bail!("Unknown {} {}", RETENTION_ENV, retention),
+        }
+    }
+}
+
+/// Append-only log of a key.
+///
+/// Offsets are given in increasing order and never reused, so an offset identifies a message
+/// for the whole life of the log. Messages given by the leader of the key never replace stored
+/// ones, only a node that takes the lead of the key replaces the messages it appended without
+/// acknowledging them, by those of the replicas it catches up from.
+///
+/// Retention drops messages below the offset every consumer has committed, but never renumbers
+/// the others: the offsets of dropped messages are kept as trimmed ranges instead.
+#[derive(Debug, Default, Clone)]
+pub struct Log {
+    entries: Vec<Entry>,
+    next_offset: u64,
+    /// Offset up to which the consumers have processed the log, if they have started.
+    committed: Option<u64>,
+    /// Offset committed by each consumer.
+    consumers: HashMap<String, u64>,
+    /// Offsets of the dropped messages, sorted and merged.
+    trimmed: Vec<Trimmed>,
+}
+
+impl Log {
+    /// Appends `msg` and returns its offset.
+    pub fn append(&mut self, msg: i64) -> u64 {
+        let offset = self.next_offset;
+
+        self.entries.push((offset, msg));
+        self.next_offset += 1;
+
+        offset
+    }
+
+    /// Stores a message whose offset was given by the leader of the key.
+    ///
+    /// A message that is already stored may have been acknowledged, so it is never replaced:
+    /// returns false if the offset holds another message, or was trimmed.
+    pub fn put(&mut self, (offset, msg): Entry) -> bool {
+        if self.is_trimmed(offset) {
+            return true;
+        }
+
+        let position = self.entries.partition_point(|(o, _)| *o < offset);
+
+        match self.entries.get(position) {
+            Some(entry) if entry.0 == offset => entry.1 == msg,
+            _ => {
+                self.entries.insert(position, (offset, msg));
+                self.next_offset = self.next_offset.max(offset + 1);
+
+                true
+            }
+        }
+    }
+
+    /// Stores a message of the replica a node catches up from when it takes the lead of the key,
+    /// replacing the one the node had at the same offset. Returns the replaced message.
+    pub fn adopt(&mut self, (offset, msg): Entry) -> Option<i64> {
+        if self.is_trimmed(offset) {
+            return None;
+        }
+
+        let position = self.entries.partition_point(|(o, _)| *o < offset);
+
+        match self.entries.get_mut(position) {
+            Some(entry) if entry.0 == offset => {
+                Some(std::mem::replace(&mut entry.1, msg)).filter(|replaced| *replaced != msg)
+            }
+            _ => {
+                self.entries.insert(position, (offset, msg));
+                self.next_offset = self.next_offset.max(offset + 1);
+
+                None
+            }
+        }
+    }
+
+    /// Returns every message from `offset` on, gaps included.
+    pub fn entries_from(&self, offset: u64) -> Vec<Entry> {
+        let start = self.entries.partition_point(|(o, _)| *o < offset);
+
+        self.entries[start..].to_vec()
+    }
+
+    pub fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    fn is_trimmed(&self, offset: u64) -> bool {
+        self.trimmed
+            .iter()
+            .any(|(first, last)| (*first..=*last).contains(&offset))
+    }
+
+    /// Returns up to `limit` messages, starting at `offset`, along with the trimmed ranges
+    /// between them.
+    ///
+    /// Only consecutive offsets are returned, trimmed ones aside, so a message that has not
+    /// arrived yet is never skipped.
+    pub fn read_from(&self, offset: u64, limit: usize) -> (Vec<Entry>, Vec<Trimmed>) {
+        let start = self.entries.partition_point(|(o, _)| *o < offset);
+        let mut entries = self.entries[start..].iter().peekable();
+
+        let start = self.trimmed.partition_point(|(_, last)| *last < offset);
+        let mut ranges = self.trimmed[start..].iter().peekable();
+
+        let mut msgs = Vec::new();
+        let mut trimmed = Vec::new();
+        let mut next = offset;
+
+        while msgs.len() < limit {
+            if let Some((first, last)) = ranges.next_if(|(first, _)| *first <= next) {
+                trimmed.push((next.max(*first), *last));
+                next = last + 1;
+            } else if let Some(entry) = entries.next_if(|(o, _)| *o == next) {
+                msgs.push(*entry);
+                next += 1;
+            } else {
+                break;
+            }
+        }
+
+        (msgs, trimmed)
+    }
+
+    /// Commits the log up to `offset` for `consumer`. Committed offsets never go back.
+    pub fn commit(&mut self, consumer: &str, offset: u64) {
+        self.committed = Some(
+            self.committed
+                .map_or(offset, |committed| committed.max(offset)),
+        );
+
+        let committed = self.consumers.entry(consumer.to_string()).or_default();
+        *committed = offset.max(*committed);
+    }
+
+    pub fn committed(&self) -> Option<u64> {
+        self.committed
+    }
+
+    /// Drops the messages below the lowest offset committed by the consumers, as `retention`
+    /// says.
+    pub fn retain(&mut self, retention: Retention) {
+        if let Some(below) = self.consumers.values().min().copied() {
+            self.trim(below, retention);
+        }
+    }
+
+    /// Drops the messages below `below`, an offset every consumer has committed, as `retention`
+    /// says.
+    pub fn trim(&mut self, below: u64, retention: Retention) {
+        let dropped: HashSet<u64> = match retention {
+            Retention::Keep => return,
+            Retention::Delete => self
+                .entries
+                .iter()
+                .take_while(|(offset, _)| *offset < below)
+                .map(|(offset, _)| *offset)
+                .collect(),
+            Retention::Compact => {
+                let mut later = HashSet::new();
+
+                self.entries
+                    .iter()
+                    .rev()
+                    .filter(|(offset, msg)| !later.insert(*msg) && *offset < below)
+                    .map(|(offset, _)| *offset)
+                    .collect()
+            }
+        };
+
+        if dropped.is_empty() {
+            return;
+        }
+
+        self.entries.retain(|(offset, _)| !dropped.contains(offset));
+
+        self.trimmed
+            .extend(dropped.into_iter().map(|offset| (offset, offset)));
+        self.trimmed.sort_unstable();
+        self.trimmed = self.trimmed.iter().fold(Vec::new(), |mut merged, range| {
+            match merged.last_mut() {
+                Some((_, last)) if range.0 <= *last + 1 => *last = (*last).max(range.1),
+                _ => merged.push(*range),
+            }
+
+            merged
+        });
+    }
+}
+
+/// Logs of every key.
+#[derive(Debug, Default, Clone)]
+pub struct Logs {
+    logs: HashMap<String, Log>,
+    retention: Retention,
+}
+
+impl Logs {
+    /// Logs whose committed messages are dropped as `retention` says.
+    pub fn new(retention: Retention) -> Self {
+        Self {
+            logs: HashMap::new(),
+            retention,
+        }
+    }
+
+    pub fn append(&mut self, key: &str, msg: i64) -> u64 {
+        self.logs.entry(key.to_string()).or_default().append(msg)
+    }
+
+    /// Returns the messages of every key from the requested offset, and the ranges trimmed
+    /// among them. Keys without any message or trimmed range from there are left out.
+    pub fn poll(
+        &self,
+        offsets: &HashMap<String, u64>,
+        limit: usize,
+    ) -> (HashMap<String, Vec<Entry>>, HashMap<String, Vec<Trimmed>>) {
+        let mut msgs = HashMap::new();
+        let mut trimmed = HashMap::new();
+
+        for (key, offset) in offsets.iter() {
+            let Some(log) = self.logs.get(key) else {
+                continue;
+            };
+
+            let (entries, ranges) = log.read_from(*offset, limit);
+
+            if !entries.is_empty() {
+                msgs.insert(key.clone(), entries);
+            }
+
+            if !ranges.is_empty() {
+                trimmed.insert(key.clone(), ranges);
+            }
+        }
+
+        (msgs, trimmed)
+    }
+
+    /// Stores messages whose offsets were given by the leader of `key`, returning false if one of
+    /// them would replace a stored message, see [`Log::put`].
+    pub fn put(&mut self, key: &str, entries: &[Entry]) -> bool {
+        let log = self.logs.entry(key.to_string()).or_default();
+
+        let mut accepted = true;
+
+        for entry in entries {
+            accepted &= log.put(*entry);
+        }
+
+        accepted
+    }
+
+    /// Stores the messages of the replica a node catches up from, returning the messages they
+    /// replaced, see [`Log::adopt`].
+    pub fn adopt(&mut self, key: &str, entries: &[Entry]) -> Vec<Entry> {
+        let log = self.logs.entry(key.to_string()).or_default();
+
+        entries
+            .iter()
+            .filter_map(|entry| Some((entry.0, log.adopt(*entry)?)))
+            .collect()
+    }
+
+    /// Returns every message of `key` from `offset` on.
+    pub fn entries_from(&self, key: &str, offset: u64) -> Vec<Entry> {
+        self.logs
+            .get(key)
+            .map(|log| log.entries_from(offset))
+            .unwrap_or_default()
+    }
+
+    /// Offset the next message appended to `key` gets.
+    pub fn next_offset(&self, key: &str) -> u64 {
+        self.logs.get(key).map_or(0, Log::next_offset)
+    }
+
+    /// Commits the offsets of `consumer`, then applies the retention to their keys.
+    pub fn commit(&mut self, consumer: &str, offsets: &HashMap<String, u64>) {
+        for (key, offset) in offsets.iter() {
+            let log = self.logs.entry(key.clone()).or_default();
+
+            log.commit(consumer, *offset);
+            log.retain(self.retention);
+        }
+    }
+
+    /// Drops the messages below the offset every consumer has committed, for every key of
+    /// `offsets`, when the commits were made through another node.
+    pub fn trim(&mut self, offsets: &HashMap<String, u64>) {
+        for (key, below) in offsets.iter() {
+            if let Some(log) = self.logs.get_mut(key) {
+                log.trim(*below, self.retention);
+            }
+        }
+    }
+
+    /// Returns the committed offset of every key of `keys` that has one.
+    pub fn committed(&self, keys: &[String]) -> HashMap<String, u64> {
+        keys.iter()
+            .filter_map(|key| Some((key.clone(), self.logs.get(key)?.committed()?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delete_drops_what_every_consumer_committed() {
+        let mut logs = Logs::new(Retention::Delete);
+        for msg in 0..5 {
+            logs.append("k", msg);
+        }
+
+        logs.commit("c1", &HashMap::from([("k".to_string(), 1)]));
+        logs.commit("c2", &HashMap::from([("k".to_string(), 3)]));
+        assert_eq!(
+            logs.poll(&HashMap::from([("k".to_string(), 0)]), 10).1["k"],
+            [(0, 0)]
+        );
+
+        // The messages c1 has not committed yet stay.
+        logs.commit("c1", &HashMap::from([("k".to_string(), 4)]));
+
+        let (msgs, trimmed) = logs.poll(&HashMap::from([("k".to_string(), 1)]), 10);
+        assert_eq!(msgs["k"], [(3, 3), (4, 4)]);
+        assert_eq!(trimmed["k"], [(1, 2)]);
+        assert_eq!(logs.append("k", 5), 5);
+    }
+
+    #[test]
+    fn put_never_replaces_a_stored_message() {
+        let mut log = Log::default();
+
+        assert!(log.put((0, 10)));
+        assert!(log.put((2, 12)));
+        assert!(log.put((0, 10)));
+        assert!(!log.put((0, 20)));
+
+        assert_eq!(log.entries_from(0), [(0, 10), (2, 12)]);
+        assert_eq!(log.next_offset(), 3);
+    }
+
+    #[test]
+    fn adopt_replaces_and_reports_messages() {
+        let mut log = Log::default();
+        log.append(10);
+        log.append(11);
+
+        assert_eq!(log.adopt((1, 21)), Some(11));
+        assert_eq!(log.adopt((1, 21)), None);
+        assert_eq!(log.adopt((2, 22)), None);
+
+        assert_eq!(log.entries_from(1), [(1, 21), (2, 22)]);
+        assert_eq!(log.append(23), 3);
+    }
+
+    #[test]
+    fn compact_keeps_the_latest_copy_of_committed_messages() {
+        let mut logs = Logs::new(Retention::Compact);
+        for msg in [1, 2, 1, 3, 2, 2] {
+            logs.append("k", msg);
+        }
+
+        // Offset 4 is committed but its message is sent again at offset 5.
+        logs.commit("c1", &HashMap::from([("k".to_string(), 5)]));
+
+        let (msgs, trimmed) = logs.poll(&HashMap::from([("k".to_string(), 0)]), 10);
+        assert_eq!(msgs["k"], [(2, 1), (3, 3), (5, 2)]);
+        assert_eq!(trimmed["k"], [(0, 1), (4, 4)]);
+    }
+}
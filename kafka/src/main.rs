@@ -0,0 +1,25 @@
+mod kv;
+mod leader;
+mod log;
+mod node;
+
+use kv::KvKafka;
+use leader::LeaderKafka;
+use node::Kafka;
+use std::env;
+
+/// Environment variable selecting where the logs live: `single` (default) keeps them in the
+/// memory of the node, for a single node cluster, `lin-kv` keeps them in Maelstrom's `lin-kv`,
+/// so any node of the cluster can serve any key, and `leader` gives every key to a node which
+/// replicates its log to the others. Every mode drops committed messages as `KAFKA_RETENTION`
+/// says.
+const MODE_ENV: &str = "KAFKA_MODE";
+
+fn main() -> anyhow::Result<()> {
+    match env::var(MODE_ENV).as_deref() {
+        Err(_) | Ok("single") => maelstrom_core::run::<Kafka>(),
+        Ok("lin-kv") => maelstrom_core::async_runtime::run::<KvKafka>(),
+        Ok("leader") => maelstrom_core::async_runtime::run::<LeaderKafka>(),
+        Ok(mode) => anyhow::bail!("Unknown {} {}", MODE_ENV, mode),
+    }
+}
@@ -0,0 +1,121 @@
+use maelstrom_core::{Context, Error, Handler, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::log::{Entry, Logs, POLL_LIMIT, Retention, Trimmed};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageBody {
+    Send {
+        key: String,
+        msg: i64,
+    },
+    SendOk {
+        offset: u64,
+    },
+    Poll {
+        offsets: HashMap<String, u64>,
+    },
+    PollOk {
+        msgs: HashMap<String, Vec<Entry>>,
+        /// Offsets dropped by the retention among the polled ones, so that consumers can tell
+        /// them apart from messages that have not arrived yet.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        trimmed: HashMap<String, Vec<Trimmed>>,
+    },
+    CommitOffsets {
+        offsets: HashMap<String, u64>,
+    },
+    CommitOffsetsOk,
+    ListCommittedOffsets {
+        keys: Vec<String>,
+    },
+    ListCommittedOffsetsOk {
+        offsets: HashMap<String, u64>,
+    },
+    /// Messages appended by the owner of their key, sent to the other nodes.
+    Replicate {
+        msgs: HashMap<String, Vec<Entry>>,
+    },
+    ReplicateOk {
+        /// Keys whose messages were refused, because the sender does not lead them as far as the
+        /// receiver knows, or because they would replace messages the receiver has.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        rejected: Vec<String>,
+    },
+    /// A message appended by the leader of its key, sent to an in-sync replica before the send
+    /// is acknowledged.
+    Append {
+        key: String,
+        entry: Entry,
+    },
+    AppendOk,
+    /// Asks a replica for every message of `key` from `offset` on, sent by a node that takes the
+    /// lead of the key.
+    Fetch {
+        key: String,
+        offset: u64,
+    },
+    FetchOk {
+        entries: Vec<Entry>,
+    },
+    /// Tells the other nodes that the sender is up.
+    Heartbeat,
+    /// Offset every consumer of each key has committed, sent by the node they committed through
+    /// to the others, which apply the retention to their copy of the logs.
+    Trim {
+        offsets: HashMap<String, u64>,
+    },
+}
+
+/// Kafka-style log kept in the memory of a single node.
+///
+/// The node sees every commit of every consumer, so it can drop the messages they have all
+/// committed, as `KAFKA_RETENTION` says.
+#[derive(Debug)]
+pub struct Kafka {
+    logs: Logs,
+}
+
+impl Handler for Kafka {
+    type Payload = MessageBody;
+
+    fn init(_ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+        Ok(Self {
+            logs: Logs::new(Retention::from_env()?),
+        })
+    }
+
+    fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let body = match req.body.payload {
+            MessageBody::Send { key, msg } => MessageBody::SendOk {
+                offset: self.logs.append(&key, msg),
+            },
+            MessageBody::Poll { offsets } => {
+                let (msgs, trimmed) = self.logs.poll(&offsets, POLL_LIMIT);
+
+                MessageBody::PollOk { msgs, trimmed }
+            }
+            MessageBody::CommitOffsets { offsets } => {
+                self.logs.commit(&req.src, &offsets);
+
+                MessageBody::CommitOffsetsOk
+            }
+            MessageBody::ListCommittedOffsets { keys } => MessageBody::ListCommittedOffsetsOk {
+                offsets: self.logs.committed(&keys),
+            },
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        };
+
+        Ok(Some(body))
+    }
+}
@@ -0,0 +1,418 @@
+//! Tokio-based alternative to the threaded runtime.
+//!
+//! Handlers are `async fn`s that run concurrently, one task per request, so they can wait on
+//! several RPCs at once. The handler API mirrors the threaded one, except that handlers only get
+//! `&self`: state shared between requests lives behind the handler's own locks.
+use anyhow::Context as _;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    debug, error,
+    error::{Error, ErrorCode, ErrorPayload},
+    ids::MsgIds,
+    input,
+    log::Logger,
+    message::{self, Body, Malformed, Message},
+    node::Node,
+    output::{self, Output},
+    rpc::RpcOptions,
+    runtime::log_failure,
+    trace, warn,
+};
+
+/// Workload logic of a node running on the async runtime.
+pub trait Handler: Sized + Send + Sync + 'static {
+    type Payload: Serialize + DeserializeOwned + Send + 'static;
+
+    /// Builds the handler once the node is initialised. Periodic tasks are registered here.
+    fn init(ctx: &Context) -> anyhow::Result<Self>;
+
+    /// Handles a request, returning the payload of the reply if there is one.
+    ///
+    /// A returned [`Error`] is sent back to the requester as an `error` message.
+    fn handle(
+        &self,
+        ctx: Context,
+        req: Message<Self::Payload>,
+    ) -> impl Future<Output = anyhow::Result<Option<Self::Payload>>> + Send;
+
+    /// Handles a message whose type is not part of `Payload`, with its body as raw JSON.
+    ///
+    /// By default it is answered with a `not-supported` error.
+    fn handle_unknown(
+        &self,
+        _ctx: Context,
+        req: Message<Value>,
+    ) -> impl Future<Output = anyhow::Result<Option<Self::Payload>>> + Send {
+        async move {
+            Err(Error::not_supported(format!(
+                "Message type {} not supported",
+                req.kind().unwrap_or("<none>")
+            ))
+            .into())
+        }
+    }
+}
+
+/// Gives handlers access to the node identity and to the runtime services.
+///
+/// It is cheap to clone, every clone shares the same node.
+#[derive(Debug, Clone)]
+pub struct Context {
+    inner: Arc<Inner>,
+    /// Logger tagged with the request or task this context was handed to.
+    logger: Logger,
+}
+
+#[derive(Debug)]
+struct Inner {
+    node: Node,
+    output: Output,
+    msg_ids: MsgIds,
+    /// Requests waiting for their reply, by msg_id.
+    pending: Mutex<HashMap<u32, oneshot::Sender<Message<Value>>>>,
+}
+
+impl Context {
+    pub fn node(&self) -> &Node {
+        &self.inner.node
+    }
+
+    /// Allocator of the msg_id of every message this node sends.
+    pub fn msg_ids(&self) -> &MsgIds {
+        &self.inner.msg_ids
+    }
+
+    /// Logger tagged with the message or task being handled.
+    pub fn log(&self) -> &Logger {
+        &self.logger
+    }
+
+    fn with_logger(&self, logger: Logger) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            logger,
+        }
+    }
+
+    /// Sends a message to `dest` without waiting for any reply.
+    pub fn send<P: Serialize>(&self, dest: impl Into<String>, payload: P) -> anyhow::Result<()> {
+        let body = Body::request(self.inner.msg_ids.next(), payload);
+
+        self.inner
+            .output
+            .write(&Message::new(self.node().node_id.clone(), dest, body))
+    }
+
+    /// Answers the request `in_reply_to` sent by `dest`.
+    ///
+    /// Replies returned by [`Handler::handle`] are sent by the runtime, this is for requests
+    /// that are answered from somewhere else, e.g. a periodic task.
+    pub fn reply<P: Serialize>(
+        &self,
+        dest: impl Into<String>,
+        in_reply_to: Option<u32>,
+        payload: P,
+    ) -> anyhow::Result<()> {
+        let body = Body::reply(self.inner.msg_ids.next(), in_reply_to, payload);
+
+        self.inner
+            .output
+            .write(&Message::new(self.node().node_id.clone(), dest, body))
+    }
+
+    /// Sends a request to `dest` and waits for the matching reply, decoded as `R`.
+    ///
+    /// An `error` reply, or the timeout of the last attempt, is returned as an [`Error`].
+    pub async fn rpc<Q, R>(
+        &self,
+        dest: impl Into<String>,
+        payload: Q,
+        options: RpcOptions,
+    ) -> Result<Message<R>, Error>
+    where
+        Q: Serialize,
+        R: Serialize + DeserializeOwned,
+    {
+        let msg_id = self.inner.msg_ids.next();
+        let dest = dest.into();
+        let msg = Message::new(
+            self.node().node_id.clone(),
+            dest.clone(),
+            Body::request(msg_id, payload),
+        );
+        let line = serde_json::to_string(&msg)
+            .map_err(|err| Error::new(ErrorCode::Crash, err.to_string()))?;
+
+        let (tx, mut rx) = oneshot::channel();
+        self.pending().insert(msg_id, tx);
+
+        for _ in 0..=options.retries {
+            if let Err(err) = self.inner.output.write_line(line.clone()) {
+                self.pending().remove(&msg_id);
+
+                return Err(Error::new(ErrorCode::Crash, err.to_string()));
+            }
+
+            if let Ok(reply) = tokio::time::timeout(options.timeout, &mut rx).await {
+                let reply = reply.map_err(|_| {
+                    Error::new(
+                        ErrorCode::Crash,
+                        "The runtime stopped before the reply arrived",
+                    )
+                })?;
+
+                return decode_reply(reply);
+            }
+
+            debug!(
+                self.logger.with("in_reply_to", msg_id).with("peer", &dest),
+                "Request timed out"
+            );
+        }
+
+        self.pending().remove(&msg_id);
+
+        Err(Error::new(
+            ErrorCode::Timeout,
+            format!(
+                "Request to {} timed out after {} attempt(s)",
+                dest,
+                options.retries + 1
+            ),
+        ))
+    }
+
+    /// Like [`Context::rpc`] with the default options, returning only the reply payload.
+    pub async fn call<Q, R>(&self, dest: impl Into<String>, payload: Q) -> Result<R, Error>
+    where
+        Q: Serialize,
+        R: Serialize + DeserializeOwned,
+    {
+        let reply = self.rpc(dest, payload, RpcOptions::default()).await?;
+
+        Ok(reply.body.payload)
+    }
+
+    /// Registers a task that runs every `period`, e.g. gossip, anti-entropy or heartbeats. The
+    /// first run happens one period after the registration.
+    ///
+    /// A run that fails is logged, it does not stop the following runs.
+    pub fn every<F, Fut>(&self, name: &'static str, period: Duration, task: F)
+    where
+        F: Fn(Context) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send,
+    {
+        let ctx = self.with_logger(self.logger.with("task", name));
+
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = task(ctx.clone()).await {
+                    error!(ctx.logger, "Periodic task failed: {:#}", err);
+                }
+            }
+        });
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, HashMap<u32, oneshot::Sender<Message<Value>>>> {
+        self.inner
+            .pending
+            .lock()
+            .expect("Pending requests poisoned")
+    }
+}
+
+fn decode_reply<R: Serialize + DeserializeOwned>(
+    reply: Message<Value>,
+) -> Result<Message<R>, Error> {
+    if reply.kind() == Some("error") {
+        return Err(reply.decode::<ErrorPayload>()?.body.payload.into());
+    }
+
+    reply.decode()
+}
+
+/// Hands a reply to the request waiting for it, and any other message to the handler in a task
+/// of its own.
+fn dispatch<H: Handler>(handler: &Arc<H>, ctx: &Context, msg: Message<Value>) {
+    let ctx = ctx.with_logger(ctx.logger.for_message(&msg));
+    trace!(ctx.logger, "Received message");
+
+    if let Some(tx) = msg
+        .body
+        .in_reply_to
+        .and_then(|in_reply_to| ctx.pending().remove(&in_reply_to))
+    {
+        // The requester may have given up in the meantime, in which case nobody wants the reply.
+        let _ = tx.send(msg);
+
+        return;
+    }
+
+    // Errors and replies that nobody waits for anymore, e.g. late answers of a service, are
+    // dropped, answering them would only start a ping-pong of errors between nodes.
+    if msg.kind() == Some("error") || msg.body.in_reply_to.is_some() {
+        debug!(ctx.logger, "Dropping reply that nobody waits for");
+
+        return;
+    }
+
+    let handler = handler.clone();
+
+    tokio::spawn(async move {
+        let (src, msg_id) = (msg.src.clone(), msg.body.msg_id);
+
+        let result = if msg.kind() == Some("init") {
+            let inner = &ctx.inner;
+
+            inner
+                .node
+                .reinit(&msg, &inner.output, &inner.msg_ids, &ctx.logger)
+                .map(|_| None)
+        } else {
+            match msg.decode::<H::Payload>() {
+                Ok(req) => handler.handle(ctx.clone(), req).await,
+                Err(error) if error.code == ErrorCode::NotSupported => {
+                    handler.handle_unknown(ctx.clone(), msg).await
+                }
+                Err(error) => Err(error.into()),
+            }
+        };
+
+        let written = match result {
+            Ok(Some(payload)) => ctx.reply(src, msg_id, payload),
+            Ok(None) => Ok(()),
+            Err(err) => {
+                let error = Error::from(&err);
+
+                log_failure(&ctx.logger, &err, &error);
+
+                // Without a msg_id the sender does not expect any reply, not even an error.
+                match msg_id {
+                    Some(_) => ctx.reply(src, msg_id, ErrorPayload::from(error)),
+                    None => Ok(()),
+                }
+            }
+        };
+
+        if let Err(err) = written {
+            debug!(ctx.logger, "Error when replying: {:#}", err);
+        }
+    });
+}
+
+/// Logs a line that is not a valid message, answering it with `malformed-request` when we know
+/// who sent it and which request it was.
+fn reject(ctx: &Context, malformed: Malformed) -> anyhow::Result<()> {
+    let logger = match &malformed.src {
+        Some(src) => ctx.logger.with("peer", src),
+        None => ctx.logger.clone(),
+    };
+
+    warn!(logger, "Dropping malformed message: {}", malformed.reason);
+
+    let (Some(src), Some(msg_id)) = (malformed.src, malformed.msg_id) else {
+        return Ok(());
+    };
+
+    let error = Error::new(ErrorCode::MalformedRequest, malformed.reason);
+
+    ctx.reply(src, Some(msg_id), ErrorPayload::from(error))
+}
+
+async fn event_loop<H: Handler>(
+    ctx: Context,
+    mut lines: mpsc::Receiver<io::Result<String>>,
+) -> anyhow::Result<()> {
+    let handler = Arc::new(H::init(&ctx)?);
+
+    while let Some(line) = lines.recv().await {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match message::parse(&line) {
+            Ok(msg) => dispatch(&handler, &ctx, msg),
+            Err(malformed) => reject(&ctx, malformed)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the handler `H` on a tokio runtime until stdin is closed.
+pub fn run<H: Handler>() -> anyhow::Result<()> {
+    run_with::<H, _, _>(input::stdin(), io::stdout())
+}
+
+/// Like [`run`], reading the messages from `input` and writing them to `out` instead of stdin
+/// and stdout, e.g. to run a node in the cluster of the `harness` feature.
+pub fn run_with<H, I, W>(mut input: I, out: W) -> anyhow::Result<()>
+where
+    H: Handler,
+    I: Iterator<Item = io::Result<String>> + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let (output, writer) = Output::start(out);
+    let msg_ids = MsgIds::default();
+    let (node, early) = Node::handshake(&mut input, &output, &msg_ids)?;
+
+    let logger = Logger::new(&node.node_id);
+    if !early.is_empty() {
+        debug!(
+            logger,
+            "Replaying {} messages received before init",
+            early.len()
+        );
+    }
+
+    // The input is read from a thread of its own, as reading stdin blocks.
+    let (tx, rx) = mpsc::channel(1024);
+    std::thread::spawn(move || {
+        for line in early.into_iter().map(Ok).chain(input) {
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let ctx = Context {
+        logger,
+        inner: Arc::new(Inner {
+            node,
+            output,
+            msg_ids,
+            pending: Mutex::new(HashMap::new()),
+        }),
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Error when building the tokio runtime")?;
+
+    let result = runtime.block_on(event_loop::<H>(ctx, rx));
+
+    // Stopping the runtime drops the tasks still holding a context, and with them the last
+    // senders of the output, which lets the writer flush what is left and stop.
+    runtime.shutdown_timeout(Duration::from_millis(100));
+
+    output::finish(result, writer)
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Error codes defined by Maelstrom.
+///
+/// Codes outside of the ones Maelstrom knows about are kept as `Custom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    /// The requested operation could not be completed within a timeout.
+    Timeout,
+    /// A client sent an RPC request to a node which does not exist.
+    NodeNotFound,
+    /// The requested operation is not supported by the current implementation.
+    NotSupported,
+    /// The operation definitely cannot be performed at this time.
+    TemporarilyUnavailable,
+    /// The client's request did not conform to the server's expectations.
+    MalformedRequest,
+    /// Indefinite failure, the operation may or may not have taken place.
+    Crash,
+    /// Definite failure, the operation did not and will never take place.
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    /// The requested operation expected some conditions to hold, and those conditions were not
+    /// met, e.g. a compare-and-set whose current value did not match.
+    PreconditionFailed,
+    /// The transaction was aborted because of a conflict with another transaction.
+    TxnConflict,
+    Custom(u32),
+}
+
+impl ErrorCode {
+    /// Whether the operation definitely did not take place.
+    pub fn is_definite(&self) -> bool {
+        !matches!(self, ErrorCode::Timeout | ErrorCode::Crash)
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Custom(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Custom(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::Timeout => write!(f, "timeout"),
+            ErrorCode::NodeNotFound => write!(f, "node-not-found"),
+            ErrorCode::NotSupported => write!(f, "not-supported"),
+            ErrorCode::TemporarilyUnavailable => write!(f, "temporarily-unavailable"),
+            ErrorCode::MalformedRequest => write!(f, "malformed-request"),
+            ErrorCode::Crash => write!(f, "crash"),
+            ErrorCode::Abort => write!(f, "abort"),
+            ErrorCode::KeyDoesNotExist => write!(f, "key-does-not-exist"),
+            ErrorCode::KeyAlreadyExists => write!(f, "key-already-exists"),
+            ErrorCode::PreconditionFailed => write!(f, "precondition-failed"),
+            ErrorCode::TxnConflict => write!(f, "txn-conflict"),
+            ErrorCode::Custom(code) => write!(f, "error-{}", code),
+        }
+    }
+}
+
+/// Error that a handler can return to answer a request with a Maelstrom `error` message.
+///
+/// Handlers return it through `anyhow`, and the runtime finds it back to build the reply. Any
+/// other error is answered with a `crash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+
+    pub fn not_supported(text: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotSupported, text)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.text)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<&anyhow::Error> for Error {
+    fn from(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<Error>() {
+            Some(error) => error.clone(),
+            None => Error::new(ErrorCode::Crash, format!("{:#}", err)),
+        }
+    }
+}
+
+/// Payload of an `error` message.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ErrorPayload {
+    Error {
+        code: ErrorCode,
+        #[serde(default)]
+        text: String,
+    },
+}
+
+impl From<Error> for ErrorPayload {
+    fn from(error: Error) -> Self {
+        ErrorPayload::Error {
+            code: error.code,
+            text: error.text,
+        }
+    }
+}
+
+impl From<ErrorPayload> for Error {
+    fn from(payload: ErrorPayload) -> Self {
+        match payload {
+            ErrorPayload::Error { code, text } => Error { code, text },
+        }
+    }
+}
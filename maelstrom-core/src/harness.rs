@@ -0,0 +1,397 @@
+//! In-process stand-in for Maelstrom, to exercise workloads without the Java binary.
+//!
+//! A [`Cluster`] runs every node on a thread of its own, connected through channels instead of
+//! stdin and stdout, and routes their messages to each other, to the in-process [`Service`]s
+//! standing in for the ones Maelstrom provides, and to the requests sent with [`Cluster::call`].
+//! [`Cluster::partition`] cuts the network between nodes, like Maelstrom's partition nemesis.
+//!
+//! Nodes are started with the `run_with` of their runtime, e.g.
+//! `Cluster::builder(3).service(LinKv::new()).start(|input, output| run_with::<MyHandler, _, _>(input, output))`.
+mod kv;
+mod tso;
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    error::{Error, ErrorCode, ErrorPayload},
+    ids::MsgIds,
+    message::{self, Body, Message},
+};
+
+pub use kv::{LinKv, LwwKv, SeqKv};
+pub use tso::LinTso;
+
+/// How long [`Cluster::call`] waits for the reply of a node.
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Node id of the requests sent with [`Cluster::call`].
+const CLIENT: &str = "c0";
+
+/// A service node of the cluster, e.g. a key-value store.
+///
+/// Services answer requests on the routing thread of the cluster, one at a time, so their
+/// operations are atomic.
+pub trait Service: Send + 'static {
+    /// Node id of the service, e.g. `lin-kv`.
+    fn id(&self) -> &str;
+
+    /// Answers a request, returning the payload of the reply. A returned [`Error`] is sent back
+    /// as an `error` message.
+    fn handle(&mut self, req: &Message<Value>) -> Result<Value, Error>;
+}
+
+/// Lines sent to a node, read by its runtime as if they came from stdin.
+#[derive(Debug)]
+pub struct NodeInput {
+    rx: mpsc::Receiver<String>,
+}
+
+impl Iterator for NodeInput {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok().map(Ok)
+    }
+}
+
+/// Where a node writes its messages, handing every complete line to the cluster.
+#[derive(Debug)]
+pub struct NodeOutput {
+    tx: mpsc::Sender<Route>,
+    buffer: Vec<u8>,
+}
+
+impl Write for NodeOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]).into_owned();
+
+            self.tx
+                .send(Route::Line(line))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Route {
+    /// A line written by a node.
+    Line(String),
+    /// A request sent with [`Cluster::call`], whose reply goes back through the sender.
+    Call(Message<Value>, mpsc::Sender<Message<Value>>),
+    /// New groups of the nodes, by node id, see [`Cluster::partition`].
+    Partition(HashMap<String, usize>),
+    Stop,
+}
+
+/// Settings of a [`Cluster`] before it starts.
+pub struct ClusterBuilder {
+    node_count: usize,
+    services: Vec<Box<dyn Service>>,
+}
+
+impl ClusterBuilder {
+    /// Adds a service the nodes can send requests to.
+    pub fn service(mut self, service: impl Service) -> Self {
+        self.services.push(Box::new(service));
+
+        self
+    }
+
+    /// Starts every node with `run`, which is given the input and output of the node, e.g.
+    /// `|input, output| run_with::<MyHandler, _, _>(input, output)`, and waits for all of them to
+    /// answer their `init`.
+    pub fn start<F>(self, run: F) -> anyhow::Result<Cluster>
+    where
+        F: Fn(NodeInput, NodeOutput) -> anyhow::Result<()> + Send + Clone + 'static,
+    {
+        let node_ids: Vec<String> = (0..self.node_count).map(|i| format!("n{}", i)).collect();
+        let (tx, rx) = mpsc::channel();
+
+        let mut inputs = HashMap::new();
+        let mut nodes = Vec::new();
+
+        for node_id in node_ids.iter() {
+            let (input_tx, input_rx) = mpsc::channel();
+            let input = NodeInput { rx: input_rx };
+            let output = NodeOutput {
+                tx: tx.clone(),
+                buffer: Vec::new(),
+            };
+            let run = run.clone();
+
+            inputs.insert(node_id.clone(), input_tx);
+            nodes.push(thread::spawn(move || run(input, output)));
+        }
+
+        let router = Router {
+            inputs,
+            services: self
+                .services
+                .into_iter()
+                .map(|service| (service.id().to_string(), service))
+                .collect(),
+            pending: HashMap::new(),
+            msg_ids: MsgIds::default(),
+            groups: HashMap::new(),
+        };
+
+        let mut cluster = Cluster {
+            router: tx,
+            router_thread: Some(thread::spawn(move || router.run(rx))),
+            nodes,
+            node_ids: node_ids.clone(),
+            msg_ids: MsgIds::default(),
+        };
+
+        for node_id in node_ids.iter() {
+            let init = json!({"type": "init", "node_id": node_id, "node_ids": node_ids});
+
+            if let Err(error) = cluster.rpc(node_id, init) {
+                // The node may have failed on its own, which says more than the timeout.
+                cluster.shutdown()?;
+
+                return Err(error.into());
+            }
+        }
+
+        Ok(cluster)
+    }
+}
+
+/// Nodes running in-process, named `n0`, `n1`... like Maelstrom does.
+///
+/// Dropping the cluster stops the nodes, [`Cluster::stop`] also waits for them and reports the
+/// first of their failures.
+pub struct Cluster {
+    router: mpsc::Sender<Route>,
+    router_thread: Option<JoinHandle<()>>,
+    nodes: Vec<JoinHandle<anyhow::Result<()>>>,
+    node_ids: Vec<String>,
+    msg_ids: MsgIds,
+}
+
+impl Cluster {
+    /// Cluster of `node_count` nodes, without any service.
+    pub fn builder(node_count: usize) -> ClusterBuilder {
+        ClusterBuilder {
+            node_count,
+            services: Vec::new(),
+        }
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// Sends a request to `dest`, a node or a service, and waits for the reply, decoded as `R`.
+    ///
+    /// An `error` reply is returned as an [`Error`], as is the lack of a reply after a few
+    /// seconds.
+    pub fn call<Q, R>(&self, dest: &str, payload: Q) -> Result<R, Error>
+    where
+        Q: Serialize,
+        R: Serialize + DeserializeOwned,
+    {
+        let payload = serde_json::to_value(payload)
+            .map_err(|err| Error::new(ErrorCode::MalformedRequest, err.to_string()))?;
+
+        Ok(self.rpc(dest, payload)?.decode::<R>()?.body.payload)
+    }
+
+    fn rpc(&self, dest: &str, payload: Value) -> Result<Message<Value>, Error> {
+        let msg = Message::new(CLIENT, dest, Body::request(self.msg_ids.next(), payload));
+        let (tx, rx) = mpsc::channel();
+
+        self.router
+            .send(Route::Call(msg, tx))
+            .map_err(|_| Error::new(ErrorCode::Crash, "The cluster is stopped"))?;
+
+        let reply = rx.recv_timeout(CALL_TIMEOUT).map_err(|_| {
+            Error::new(
+                ErrorCode::Timeout,
+                format!("{} did not answer within {:?}", dest, CALL_TIMEOUT),
+            )
+        })?;
+
+        if reply.kind() == Some("error") {
+            return Err(reply.decode::<ErrorPayload>()?.body.payload.into());
+        }
+
+        Ok(reply)
+    }
+
+    /// Cuts the network between `groups` of nodes: a node only gets the messages of the nodes of
+    /// its group from now on, the others are lost. Nodes left out of every group, services and
+    /// calls still reach every node.
+    pub fn partition(&self, groups: &[&[&str]]) {
+        let groups = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, nodes)| nodes.iter().map(move |node| (node.to_string(), group)))
+            .collect();
+
+        let _ = self.router.send(Route::Partition(groups));
+    }
+
+    /// Undoes [`Cluster::partition`].
+    pub fn heal(&self) {
+        let _ = self.router.send(Route::Partition(HashMap::new()));
+    }
+
+    /// Stops the nodes and waits for them, returning the first error a node stopped with.
+    pub fn stop(mut self) -> anyhow::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> anyhow::Result<()> {
+        let Some(router_thread) = self.router_thread.take() else {
+            return Ok(());
+        };
+
+        // The router drops the inputs of the nodes when it stops, which makes them stop too.
+        let _ = self.router.send(Route::Stop);
+        let _ = router_thread.join();
+
+        let mut result = Ok(());
+
+        for (node_id, node) in self.node_ids.iter().zip(self.nodes.drain(..)) {
+            let stopped = match node.join() {
+                Ok(stopped) => stopped,
+                Err(_) => Err(anyhow::anyhow!("Node thread panicked")),
+            };
+
+            if let Err(err) = stopped
+                && result.is_ok()
+            {
+                result = Err(err.context(format!("Node {} failed", node_id)));
+            }
+        }
+
+        result
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+/// Delivers every message of the cluster from a single thread.
+struct Router {
+    inputs: HashMap<String, mpsc::Sender<String>>,
+    services: HashMap<String, Box<dyn Service>>,
+    /// Requests sent with [`Cluster::call`] waiting for their reply, by msg_id.
+    pending: HashMap<u32, mpsc::Sender<Message<Value>>>,
+    /// Allocator of the msg_id of the replies of the services.
+    msg_ids: MsgIds,
+    /// Group of each partitioned node.
+    groups: HashMap<String, usize>,
+}
+
+impl Router {
+    fn run(mut self, routes: mpsc::Receiver<Route>) {
+        while let Ok(route) = routes.recv() {
+            match route {
+                Route::Line(line) => {
+                    // A line that is not a message has nobody to go to.
+                    if let Ok(msg) = message::parse(&line) {
+                        self.deliver(msg);
+                    }
+                }
+                Route::Call(msg, reply) => {
+                    if let Some(msg_id) = msg.body.msg_id {
+                        self.pending.insert(msg_id, reply);
+                    }
+
+                    self.deliver(msg);
+                }
+                Route::Partition(groups) => self.groups = groups,
+                Route::Stop => return,
+            }
+        }
+    }
+
+    fn deliver(&mut self, msg: Message<Value>) {
+        if let (Some(src), Some(dest)) = (self.groups.get(&msg.src), self.groups.get(&msg.dest))
+            && src != dest
+        {
+            return;
+        }
+
+        if let Some(input) = self.inputs.get(&msg.dest) {
+            // A node that has stopped does not read its input anymore.
+            if let Ok(line) = serde_json::to_string(&msg) {
+                let _ = input.send(line);
+            }
+
+            return;
+        }
+
+        if let Some(service) = self.services.get_mut(&msg.dest) {
+            let payload = service.handle(&msg).or_else(|error| {
+                serde_json::to_value(ErrorPayload::from(error))
+                    .map_err(|err| Error::new(ErrorCode::Crash, err.to_string()))
+            });
+
+            if let Ok(payload) = payload {
+                let body = Body::reply(self.msg_ids.next(), msg.body.msg_id, payload);
+
+                self.deliver(Message::new(msg.dest, msg.src, body));
+            }
+
+            return;
+        }
+
+        // Anything else is a reply to a call, or is addressed to a node that does not exist.
+        if let Some(reply) = msg
+            .body
+            .in_reply_to
+            .and_then(|in_reply_to| self.pending.remove(&in_reply_to))
+        {
+            let _ = reply.send(msg);
+        }
+    }
+}
+
+/// Small xorshift generator, so that the services can be seeded and replayed.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves 0.
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        self.0
+    }
+
+    /// Returns a number in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+}
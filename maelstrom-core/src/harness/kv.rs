@@ -0,0 +1,412 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::{Rng, Service};
+use crate::{
+    error::{Error, ErrorCode},
+    message::Message,
+    services::{KvPayload, LIN_KV, LWW_KV, SEQ_KV},
+};
+
+/// Operations of a key-value stand-in. Keys are compared by their JSON form.
+trait Store {
+    fn read(&mut self, src: &str, key: String) -> Option<Value>;
+
+    fn write(&mut self, src: &str, key: String, value: Value);
+
+    fn cas(
+        &mut self,
+        src: &str,
+        key: String,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), Error>;
+}
+
+/// Checks that a compare-and-set from `from` can apply to the `current` value of its key.
+fn check_cas(
+    current: Option<&Value>,
+    from: &Value,
+    create_if_not_exists: bool,
+) -> Result<(), Error> {
+    match current {
+        None if !create_if_not_exists => Err(key_does_not_exist()),
+        Some(current) if current != from => Err(Error::new(
+            ErrorCode::PreconditionFailed,
+            format!("Expected {}, but had {}", from, current),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Answers a key-value request with `store`.
+fn serve(store: &mut impl Store, req: &Message<Value>) -> Result<Value, Error> {
+    let src = req.src.as_str();
+
+    let reply = match req.decode::<KvPayload>()?.body.payload {
+        KvPayload::Read { key } => {
+            let value = store
+                .read(src, key.to_string())
+                .ok_or_else(key_does_not_exist)?;
+
+            KvPayload::ReadOk { value }
+        }
+        KvPayload::Write { key, value } => {
+            store.write(src, key.to_string(), value);
+
+            KvPayload::WriteOk
+        }
+        KvPayload::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        } => {
+            store.cas(src, key.to_string(), from, to, create_if_not_exists)?;
+
+            KvPayload::CasOk
+        }
+        payload => {
+            return Err(Error::not_supported(format!(
+                "Message {:?} not supported",
+                payload
+            )));
+        }
+    };
+
+    serde_json::to_value(reply).map_err(|err| Error::new(ErrorCode::Crash, err.to_string()))
+}
+
+fn key_does_not_exist() -> Error {
+    Error::new(ErrorCode::KeyDoesNotExist, "Key does not exist")
+}
+
+/// Linearizable key-value store: every operation sees every operation acknowledged before it.
+#[derive(Debug, Default)]
+pub struct LinKv {
+    data: HashMap<String, Value>,
+}
+
+impl LinKv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Store for LinKv {
+    fn read(&mut self, _src: &str, key: String) -> Option<Value> {
+        self.data.get(&key).cloned()
+    }
+
+    fn write(&mut self, _src: &str, key: String, value: Value) {
+        self.data.insert(key, value);
+    }
+
+    fn cas(
+        &mut self,
+        src: &str,
+        key: String,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), Error> {
+        check_cas(self.data.get(&key), &from, create_if_not_exists)?;
+        self.write(src, key, to);
+
+        Ok(())
+    }
+}
+
+impl Service for LinKv {
+    fn id(&self) -> &str {
+        LIN_KV
+    }
+
+    fn handle(&mut self, req: &Message<Value>) -> Result<Value, Error> {
+        serve(self, req)
+    }
+}
+
+/// Sequentially consistent key-value store.
+///
+/// Writes and compare-and-sets apply to the latest state, but a read may return any state
+/// between the last one its client has seen and the latest, so it can be stale. A client never
+/// goes back in time: its reads and writes are always ordered after its previous ones.
+#[derive(Debug)]
+pub struct SeqKv {
+    /// Every value each key had, oldest first, along with the number of writes before it.
+    versions: HashMap<String, Vec<(u64, Value)>>,
+    /// Number of writes applied so far.
+    latest: u64,
+    /// Position of the last state seen by each client.
+    seen: HashMap<String, u64>,
+    stale_reads: bool,
+    rng: Rng,
+}
+
+impl SeqKv {
+    /// Store whose stale reads are drawn from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            versions: HashMap::new(),
+            latest: 0,
+            seen: HashMap::new(),
+            stale_reads: true,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Whether reads may return stale values, true by default. Without them the store is
+    /// linearizable.
+    pub fn stale_reads(mut self, stale_reads: bool) -> Self {
+        self.stale_reads = stale_reads;
+
+        self
+    }
+
+    fn value_at(&self, key: &str, at: u64) -> Option<Value> {
+        self.versions
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|(version, _)| *version <= at)
+            .map(|(_, value)| value.clone())
+    }
+}
+
+impl Store for SeqKv {
+    fn read(&mut self, src: &str, key: String) -> Option<Value> {
+        let seen = self.seen.get(src).copied().unwrap_or(0);
+        let at = if self.stale_reads {
+            seen + self.rng.below(self.latest - seen + 1)
+        } else {
+            self.latest
+        };
+
+        self.seen.insert(src.to_string(), at);
+
+        self.value_at(&key, at)
+    }
+
+    fn write(&mut self, src: &str, key: String, value: Value) {
+        self.latest += 1;
+        self.versions
+            .entry(key)
+            .or_default()
+            .push((self.latest, value));
+        self.seen.insert(src.to_string(), self.latest);
+    }
+
+    fn cas(
+        &mut self,
+        src: &str,
+        key: String,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), Error> {
+        // Like writes, compare-and-sets apply to the latest state.
+        self.seen.insert(src.to_string(), self.latest);
+
+        check_cas(
+            self.value_at(&key, self.latest).as_ref(),
+            &from,
+            create_if_not_exists,
+        )?;
+        self.write(src, key, to);
+
+        Ok(())
+    }
+}
+
+impl Service for SeqKv {
+    fn id(&self) -> &str {
+        SEQ_KV
+    }
+
+    fn handle(&mut self, req: &Message<Value>) -> Result<Value, Error> {
+        serve(self, req)
+    }
+}
+
+/// Last-write-wins key-value store, replicated without coordination.
+///
+/// Every request goes to a random replica, and replicas only exchange their data from time to
+/// time. Values are stamped with the clock of the replica that took the write, clocks drifting
+/// apart, and the highest stamp wins when replicas merge. Reads can be stale, and a write can be
+/// lost to a concurrent one, or even to an older one stamped by a faster clock.
+#[derive(Debug)]
+pub struct LwwKv {
+    /// Value of each key on each replica, with the stamp of its write.
+    replicas: Vec<HashMap<String, (Stamp, Value)>>,
+    clocks: Vec<u64>,
+    rng: Rng,
+}
+
+/// Clock of the replica that took a write, and the replica to break ties.
+type Stamp = (u64, usize);
+
+impl LwwKv {
+    /// Store of 3 replicas, whose routing, clocks and merges are drawn from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            replicas: Vec::new(),
+            clocks: Vec::new(),
+            rng: Rng::new(seed),
+        }
+        .replicas(3)
+    }
+
+    /// Number of replicas. A single replica never loses a write.
+    pub fn replicas(mut self, count: usize) -> Self {
+        let count = count.max(1);
+
+        self.replicas = vec![HashMap::new(); count];
+        self.clocks = vec![0; count];
+
+        self
+    }
+
+    /// Picks the replica of the next request, merging it with another one half of the time.
+    fn replica(&mut self) -> usize {
+        let count = self.replicas.len() as u64;
+        let replica = self.rng.below(count) as usize;
+
+        if self.rng.below(2) == 0 {
+            let other = self.rng.below(count) as usize;
+            self.merge(replica, other);
+        }
+
+        replica
+    }
+
+    fn write_at(&mut self, replica: usize, key: String, value: Value) {
+        // Clocks tick at different rates, so they drift apart.
+        self.clocks[replica] += 1 + self.rng.below(3);
+        let stamp = (self.clocks[replica], replica);
+
+        let data = &mut self.replicas[replica];
+        if data.get(&key).is_none_or(|(current, _)| *current < stamp) {
+            data.insert(key, (stamp, value));
+        }
+    }
+
+    fn merge(&mut self, a: usize, b: usize) {
+        for (key, (stamp, value)) in self.replicas[b].clone() {
+            let newer = self.replicas[a]
+                .get(&key)
+                .is_none_or(|(current, _)| *current < stamp);
+
+            if newer {
+                self.replicas[a].insert(key, (stamp, value));
+            }
+        }
+
+        let merged = self.replicas[a].clone();
+        self.replicas[b] = merged;
+    }
+}
+
+impl Store for LwwKv {
+    fn read(&mut self, _src: &str, key: String) -> Option<Value> {
+        let replica = self.replica();
+
+        self.replicas[replica]
+            .get(&key)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn write(&mut self, _src: &str, key: String, value: Value) {
+        let replica = self.replica();
+
+        self.write_at(replica, key, value);
+    }
+
+    /// A compare-and-set only looks at the replica it goes to.
+    fn cas(
+        &mut self,
+        _src: &str,
+        key: String,
+        from: Value,
+        to: Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), Error> {
+        let replica = self.replica();
+
+        check_cas(
+            self.replicas[replica].get(&key).map(|(_, value)| value),
+            &from,
+            create_if_not_exists,
+        )?;
+        self.write_at(replica, key, to);
+
+        Ok(())
+    }
+}
+
+impl Service for LwwKv {
+    fn id(&self) -> &str {
+        LWW_KV
+    }
+
+    fn handle(&mut self, req: &Message<Value>) -> Result<Value, Error> {
+        serve(self, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn lww_reads_can_be_stale() {
+        let mut kv = LwwKv::new(7);
+        let mut stale = 0;
+
+        for i in 0..100 {
+            kv.write("c0", "k".to_string(), json!(i));
+
+            match kv.read("c0", "k".to_string()) {
+                Some(value) if value == json!(i) => {}
+                Some(value) => {
+                    assert!(value.as_u64().is_some_and(|value| value < i));
+                    stale += 1;
+                }
+                None => stale += 1,
+            }
+        }
+
+        assert!(stale > 0);
+    }
+
+    #[test]
+    fn lww_merges_keep_the_last_write() {
+        let mut kv = LwwKv::new(7);
+        kv.write_at(0, "k".to_string(), json!("a"));
+        kv.write_at(1, "k".to_string(), json!("b"));
+
+        let last = [0, 1]
+            .into_iter()
+            .map(|replica| kv.replicas[replica]["k"].clone())
+            .max_by_key(|(stamp, _)| *stamp)
+            .unwrap();
+
+        kv.merge(0, 1);
+
+        assert_eq!(kv.replicas[0]["k"], last);
+        assert_eq!(kv.replicas[1]["k"], last);
+    }
+
+    #[test]
+    fn a_single_lww_replica_never_loses_a_write() {
+        let mut kv = LwwKv::new(7).replicas(1);
+
+        for i in 0..10 {
+            kv.write("c0", "k".to_string(), json!(i));
+            assert_eq!(kv.read("c1", "k".to_string()), Some(json!(i)));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use serde_json::Value;
+
+use super::Service;
+use crate::{
+    error::{Error, ErrorCode},
+    message::Message,
+    services::{LIN_TSO, TsoPayload},
+};
+
+/// Linearizable timestamp oracle: every timestamp is greater than all the ones handed out before.
+#[derive(Debug, Default)]
+pub struct LinTso {
+    next: u64,
+}
+
+impl LinTso {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Oracle whose first timestamp is `ts`.
+    pub fn starting_at(ts: u64) -> Self {
+        Self { next: ts }
+    }
+}
+
+impl Service for LinTso {
+    fn id(&self) -> &str {
+        LIN_TSO
+    }
+
+    fn handle(&mut self, req: &Message<Value>) -> Result<Value, Error> {
+        match req.decode::<TsoPayload>()?.body.payload {
+            TsoPayload::Ts => {
+                let ts = self.next;
+                self.next += 1;
+
+                serde_json::to_value(TsoPayload::TsOk { ts })
+                    .map_err(|err| Error::new(ErrorCode::Crash, err.to_string()))
+            }
+            payload => Err(Error::not_supported(format!(
+                "Message {:?} not supported",
+                payload
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::message::Body;
+
+    fn ts(tso: &mut LinTso, msg_id: u32) -> u64 {
+        let req = Message::new("n0", LIN_TSO, Body::request(msg_id, json!({"type": "ts"})));
+
+        tso.handle(&req).unwrap()["ts"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn timestamps_only_go_up() {
+        let mut tso = LinTso::starting_at(10);
+
+        let stamps: Vec<u64> = (0..5).map(|msg_id| ts(&mut tso, msg_id)).collect();
+
+        assert_eq!(stamps[0], 10);
+        assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,19 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU32, Ordering},
+};
+
+/// Allocator of the `msg_id` of every message sent by a node, requests and replies alike.
+///
+/// Ids are unique and increase monotonically for the lifetime of the node. Clones share the same
+/// counter, so the allocator can be handed to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct MsgIds {
+    last: Arc<AtomicU32>,
+}
+
+impl MsgIds {
+    pub fn next(&self) -> u32 {
+        self.last.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
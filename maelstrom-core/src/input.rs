@@ -0,0 +1,24 @@
+use std::io;
+
+/// Lines of stdin, without their line break.
+///
+/// Unlike `io::stdin().lines()` it can be moved to another thread, as stdin is locked for each
+/// line instead of once. Lines buffered by a read stay in the buffer of stdin for the next one.
+pub(crate) fn stdin() -> impl Iterator<Item = io::Result<String>> + Send + 'static {
+    let stdin = io::stdin();
+
+    std::iter::from_fn(move || {
+        let mut line = String::new();
+
+        match stdin.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                let len = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(len);
+
+                Some(Ok(line))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    })
+}
@@ -0,0 +1,27 @@
+//! Building blocks shared by every Maelstrom workload: the message envelope, the init handshake,
+//! the [`Handler`] trait each workload implements and the event loop that drives it.
+#[cfg(feature = "async")]
+pub mod async_runtime;
+mod error;
+#[cfg(feature = "harness")]
+pub mod harness;
+mod ids;
+mod input;
+mod log;
+mod message;
+mod node;
+mod output;
+mod rpc;
+mod runtime;
+pub mod services;
+mod timer;
+mod topology;
+
+pub use error::{Error, ErrorCode};
+pub use ids::MsgIds;
+pub use log::{LOG_ENV, Level, Logger};
+pub use message::{Body, Message};
+pub use node::Node;
+pub use rpc::RpcOptions;
+pub use runtime::{Context, Handler, run, run_with};
+pub use topology::Topology;
@@ -0,0 +1,173 @@
+use serde_json::Value;
+use std::{
+    env, fmt,
+    io::{self, Write},
+};
+
+use crate::message::Message;
+
+/// Environment variable holding the most verbose level to log, e.g. `MAELSTROM_LOG=debug`.
+/// `off` disables logging, and anything else falls back to `info`.
+pub const LOG_ENV: &str = "MAELSTROM_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn parse(level: &str) -> Option<Option<Self>> {
+        match level.to_ascii_lowercase().as_str() {
+            "off" => Some(None),
+            "error" => Some(Some(Level::Error)),
+            "warn" => Some(Some(Level::Warn)),
+            "info" => Some(Some(Level::Info)),
+            "debug" => Some(Some(Level::Debug)),
+            "trace" => Some(Some(Level::Trace)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warn => write!(f, "warn"),
+            Level::Info => write!(f, "info"),
+            Level::Debug => write!(f, "debug"),
+            Level::Trace => write!(f, "trace"),
+        }
+    }
+}
+
+/// Writes leveled records to stderr, which Maelstrom keeps in a log file per node.
+///
+/// Records are single `key=value` lines tagged with the node id, plus the type, ids and peer of
+/// the message being handled when there is one:
+///
+/// ```text
+/// level=debug node=n1 type=gossip msg_id=4 peer=n2 msg="3 new messages"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Logger {
+    max_level: Option<Level>,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Logger {
+    /// Builds the logger of `node_id`, taking its level from [`LOG_ENV`].
+    pub fn new(node_id: &str) -> Self {
+        let max_level = env::var(LOG_ENV)
+            .ok()
+            .and_then(|level| Level::parse(&level))
+            .unwrap_or(Some(Level::Info));
+
+        Self {
+            max_level,
+            fields: vec![("node", node_id.to_string())],
+        }
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        self.max_level.is_some_and(|max_level| level <= max_level)
+    }
+
+    /// Returns a logger whose records also carry `key=value`.
+    pub fn with(&self, key: &'static str, value: impl fmt::Display) -> Self {
+        let mut logger = self.clone();
+        logger.fields.push((key, value.to_string()));
+
+        logger
+    }
+
+    /// Returns a logger tagged with the type, ids and sender of `msg`.
+    pub fn for_message(&self, msg: &Message<Value>) -> Self {
+        let mut logger = self.clone();
+
+        if let Some(kind) = msg.kind() {
+            logger.fields.push(("type", kind.to_string()));
+        }
+        if let Some(msg_id) = msg.body.msg_id {
+            logger.fields.push(("msg_id", msg_id.to_string()));
+        }
+        if let Some(in_reply_to) = msg.body.in_reply_to {
+            logger.fields.push(("in_reply_to", in_reply_to.to_string()));
+        }
+        logger.fields.push(("peer", msg.src.clone()));
+
+        logger
+    }
+
+    pub fn log(&self, level: Level, args: fmt::Arguments<'_>) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        let mut record = format!("level={}", level);
+
+        for (key, value) in self.fields.iter() {
+            record.push_str(&format!(" {}={}", key, quote(value)));
+        }
+        record.push_str(&format!(" msg={}", quote(&args.to_string())));
+
+        // Logging must never take the node down, so a failed write is ignored.
+        let _ = writeln!(io::stderr().lock(), "{}", record);
+    }
+}
+
+/// Quotes a value when it would otherwise not read back as a single field.
+fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '=')
+    {
+        return value.to_string();
+    }
+
+    format!("{:?}", value)
+}
+
+/// Logs a record at the given level when it is enabled, e.g.
+/// `log!(ctx.log(), Level::Debug, "merged {} values", count)`.
+#[macro_export]
+macro_rules! log {
+    ($logger:expr, $level:expr, $($arg:tt)+) => {{
+        let logger: &$crate::Logger = &$logger;
+        let level = $level;
+
+        if logger.enabled(level) {
+            logger.log(level, format_args!($($arg)+));
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($logger:expr, $($arg:tt)+) => { $crate::log!($logger, $crate::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($logger:expr, $($arg:tt)+) => { $crate::log!($logger, $crate::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($logger:expr, $($arg:tt)+) => { $crate::log!($logger, $crate::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($logger:expr, $($arg:tt)+) => { $crate::log!($logger, $crate::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($logger:expr, $($arg:tt)+) => { $crate::log!($logger, $crate::Level::Trace, $($arg)+) };
+}
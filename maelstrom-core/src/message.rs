@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+use crate::error::{Error, ErrorCode};
+
+/// Envelope of every message exchanged through Maelstrom.
+///
+/// `P` is the workload payload, usually an enum tagged by `type`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Message<P> {
+    pub src: String,
+    pub dest: String,
+    pub body: Body<P>,
+    /// Envelope fields we do not know about, e.g. the `id` Maelstrom gives to every message.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl<P> Message<P> {
+    pub fn new(src: impl Into<String>, dest: impl Into<String>, body: Body<P>) -> Self {
+        Self {
+            src: src.into(),
+            dest: dest.into(),
+            body,
+            extra: Map::new(),
+        }
+    }
+}
+
+/// Body of a message.
+///
+/// `msg_id` and `in_reply_to` are common to every message type, so they live here instead of
+/// being repeated on every variant of the workload payload.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Body<P> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<u32>,
+    #[serde(flatten)]
+    pub payload: P,
+    /// Body fields that are not part of the payload type.
+    ///
+    /// They can not be told apart from the payload fields while deserializing, so the runtime
+    /// fills them in when it decodes the payload of an inbound message.
+    #[serde(flatten, skip_deserializing)]
+    pub extra: Map<String, Value>,
+}
+
+impl<P> Body<P> {
+    pub fn new(payload: P) -> Self {
+        Self {
+            msg_id: None,
+            in_reply_to: None,
+            payload,
+            extra: Map::new(),
+        }
+    }
+
+    pub fn request(msg_id: u32, payload: P) -> Self {
+        Self {
+            msg_id: Some(msg_id),
+            ..Self::new(payload)
+        }
+    }
+
+    pub fn reply(msg_id: u32, in_reply_to: Option<u32>, payload: P) -> Self {
+        Self {
+            msg_id: Some(msg_id),
+            in_reply_to,
+            ..Self::new(payload)
+        }
+    }
+}
+
+/// A line of input that is not a valid Maelstrom message.
+#[derive(Debug, Clone)]
+pub(crate) struct Malformed {
+    /// Sender and `msg_id` of the message, when they can still be found in the line.
+    pub src: Option<String>,
+    pub msg_id: Option<u32>,
+    pub reason: String,
+}
+
+/// Parses the envelope of a line of input, leaving the payload as raw JSON.
+pub(crate) fn parse(line: &str) -> Result<Message<Value>, Malformed> {
+    let value: Value = serde_json::from_str(line).map_err(|err| Malformed {
+        src: scan_field(line, "src").map(String::from),
+        msg_id: scan_field(line, "msg_id").and_then(|msg_id| msg_id.parse().ok()),
+        reason: format!("Invalid JSON: {}", err),
+    })?;
+
+    let src = value.get("src").and_then(Value::as_str).map(String::from);
+    let msg_id = value
+        .get("body")
+        .and_then(|body| body.get("msg_id"))
+        .and_then(Value::as_u64)
+        .and_then(|msg_id| u32::try_from(msg_id).ok());
+
+    serde_json::from_value(value).map_err(|err| Malformed {
+        src,
+        msg_id,
+        reason: format!("Invalid envelope: {}", err),
+    })
+}
+
+/// Finds the raw value of `key` in a line that is not valid JSON. Only strings and unsigned
+/// integers are recognised, which is enough to recover `src` and `msg_id`.
+fn scan_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("\"{}\"", key);
+    let rest = &line[line.find(&pattern)? + pattern.len()..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+
+    match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next(),
+        None => {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+
+            (end > 0).then(|| &rest[..end])
+        }
+    }
+}
+
+impl Message<Value> {
+    /// Decodes the raw payload into the type expected by the receiver.
+    ///
+    /// Fails with `not-supported` when the payload type has no variant for the message type, and
+    /// with `malformed-request` when the variant exists but its fields do not match. The body
+    /// fields that are not part of the decoded payload are kept in [`Body::extra`].
+    pub(crate) fn decode<P: Serialize + DeserializeOwned>(&self) -> Result<Message<P>, Error> {
+        let payload: P = serde_json::from_value(self.body.payload.clone()).map_err(|err| {
+            let text = format!("Cannot decode message: {}", err);
+
+            // serde reports an unknown `type` tag as an unknown variant.
+            if err.to_string().starts_with("unknown variant") {
+                Error::not_supported(text)
+            } else {
+                Error::new(ErrorCode::MalformedRequest, text)
+            }
+        })?;
+
+        let decoded = serde_json::to_value(&payload).unwrap_or(Value::Null);
+        let mut extra = self.body.extra.clone();
+
+        if let Value::Object(fields) = &self.body.payload {
+            for (key, value) in fields {
+                if decoded.get(key).is_none() {
+                    extra.insert(key.clone(), value.clone());
+                }
+            }
+        }
+
+        Ok(Message {
+            src: self.src.clone(),
+            dest: self.dest.clone(),
+            body: Body {
+                msg_id: self.body.msg_id,
+                in_reply_to: self.body.in_reply_to,
+                payload,
+                extra,
+            },
+            extra: self.extra.clone(),
+        })
+    }
+
+    /// Type of the message, as found in its body.
+    pub fn kind(&self) -> Option<&str> {
+        self.body.payload.get("type").and_then(Value::as_str)
+    }
+}
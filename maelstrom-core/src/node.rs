@@ -0,0 +1,299 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+
+use crate::{
+    error::{Error, ErrorCode, ErrorPayload},
+    ids::MsgIds,
+    log::Logger,
+    message::{self, Body, Message},
+    output::Output,
+    warn,
+};
+
+/// How many lines received before `init` are kept to be handled once the node is initialised.
+/// Past that, messages are answered with `temporarily-unavailable`, and lines that are not valid
+/// messages are dropped.
+const MAX_EARLY_MESSAGES: usize = 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum InitPayload {
+    Init {
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+    InitOk,
+}
+
+/// Identity of the current node and membership of the cluster, as given by Maelstrom in the
+/// `init` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub node_id: String,
+    /// Every node of the cluster, this one included, sorted so that all nodes agree on the order.
+    pub node_ids: Vec<String>,
+    /// Position of `node_id` in `node_ids`.
+    pub index: usize,
+}
+
+impl Node {
+    fn new(node_id: String, mut node_ids: Vec<String>) -> Result<Self, Error> {
+        node_ids.sort();
+        node_ids.dedup();
+
+        let index = node_ids
+            .iter()
+            .position(|id| *id == node_id)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorCode::MalformedRequest,
+                    format!("Node {} is not part of {:?}", node_id, node_ids),
+                )
+            })?;
+
+        Ok(Self {
+            node_id,
+            node_ids,
+            index,
+        })
+    }
+
+    /// Node that owns `key` when keys are spread over the cluster.
+    ///
+    /// Keys are dealt over `node_ids` in the order [`Topology::Ring`](crate::Topology::Ring)
+    /// links them: a key that is a number, as Maelstrom's are, goes to the node at that position,
+    /// modulo the cluster size, so consecutive keys have consecutive owners. Other keys are placed
+    /// by a hash that is stable across processes. It only depends on the key and on the sorted
+    /// membership, so every node agrees on it.
+    pub fn owner(&self, key: &str) -> &str {
+        let position = key.parse::<u64>().unwrap_or_else(|_| fnv1a(key.as_bytes()));
+
+        &self.node_ids[(position % self.node_ids.len() as u64) as usize]
+    }
+
+    /// Every node of the cluster except this one.
+    pub fn peers(&self) -> impl Iterator<Item = &String> {
+        self.node_ids.iter().filter(|id| **id != self.node_id)
+    }
+
+    /// Reads `lines` until the `init` message, answers it with `init_ok` and returns the node
+    /// along with the lines received before it, which are meant to be handled next.
+    pub(crate) fn handshake(
+        lines: &mut impl Iterator<Item = io::Result<String>>,
+        output: &Output,
+        msg_ids: &MsgIds,
+    ) -> anyhow::Result<(Self, Vec<String>)> {
+        let mut early = Vec::new();
+
+        for line in lines {
+            let line = line.context("Error when reading the init message")?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // Lines that are not valid messages are kept too, so they are rejected like any
+            // other malformed message once the node can answer.
+            let Ok(msg) = message::parse(&line) else {
+                if early.len() < MAX_EARLY_MESSAGES {
+                    early.push(line);
+                }
+
+                continue;
+            };
+
+            if msg.kind() == Some("init") {
+                match Self::from_init(&msg) {
+                    Ok(node) => {
+                        node.init_ok(&msg, output, msg_ids)?;
+
+                        return Ok((node, early));
+                    }
+                    // Maelstrom may still send a valid init, so the node keeps waiting for it.
+                    Err(error) => {
+                        let error = Error::new(ErrorCode::MalformedRequest, error.text);
+
+                        if msg.body.msg_id.is_some() {
+                            let body = Body::reply(
+                                msg_ids.next(),
+                                msg.body.msg_id,
+                                ErrorPayload::from(error),
+                            );
+
+                            output.write(&Message::new(msg.dest, msg.src, body))?;
+                        }
+
+                        continue;
+                    }
+                }
+            }
+
+            if early.len() < MAX_EARLY_MESSAGES {
+                early.push(line);
+            } else if msg.body.msg_id.is_some() {
+                let error = Error::new(
+                    ErrorCode::TemporarilyUnavailable,
+                    "Node is not initialised yet",
+                );
+                let body = Body::reply(msg_ids.next(), msg.body.msg_id, ErrorPayload::from(error));
+
+                output.write(&Message::new(msg.dest, msg.src, body))?;
+            }
+        }
+
+        anyhow::bail!("Input closed before the init message")
+    }
+
+    /// Answers an `init` received after the handshake. Maelstrom only sends one, so a different
+    /// membership is logged and ignored.
+    pub(crate) fn reinit(
+        &self,
+        msg: &Message<Value>,
+        output: &Output,
+        msg_ids: &MsgIds,
+        logger: &Logger,
+    ) -> anyhow::Result<()> {
+        let node = Self::from_init(msg)?;
+
+        if node != *self {
+            warn!(
+                logger,
+                "Ignoring init as {} of {:?}, the node is already {} of {:?}",
+                node.node_id,
+                node.node_ids,
+                self.node_id,
+                self.node_ids
+            );
+        }
+
+        self.init_ok(msg, output, msg_ids)
+    }
+
+    fn from_init(msg: &Message<Value>) -> Result<Self, Error> {
+        match msg.decode::<InitPayload>()?.body.payload {
+            InitPayload::Init { node_id, node_ids } => Self::new(node_id, node_ids),
+            InitPayload::InitOk => Err(Error::new(
+                ErrorCode::MalformedRequest,
+                "Expected an init message",
+            )),
+        }
+    }
+
+    fn init_ok(
+        &self,
+        init: &Message<Value>,
+        output: &Output,
+        msg_ids: &MsgIds,
+    ) -> anyhow::Result<()> {
+        let reply = Message::new(
+            self.node_id.clone(),
+            init.src.clone(),
+            Body::reply(msg_ids.next(), init.body.msg_id, InitPayload::InitOk),
+        );
+
+        output.write(&reply)
+    }
+}
+
+/// 64-bit FNV-1a hash, which unlike the hasher of the standard library is stable across
+/// processes and releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// Output that can still be read once the writer thread has stopped.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn handshake_rejects_malformed_init_and_waits_for_a_valid_one() {
+        let buffer = Buffer::default();
+        let (output, writer) = Output::start(buffer.clone());
+        let mut lines = [
+            r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1"}}"#,
+            r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":2,"node_id":"n1","node_ids":["n0"]}}"#,
+            r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":3,"node_id":"n1","node_ids":["n0","n1"]}}"#,
+        ]
+        .into_iter()
+        .map(|line| Ok(line.to_string()));
+
+        let (node, early) = Node::handshake(&mut lines, &output, &MsgIds::default()).unwrap();
+        drop(output);
+        writer.join().unwrap().unwrap();
+
+        assert_eq!(node.node_id, "n1");
+        assert_eq!(node.index, 1);
+        assert!(early.is_empty());
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let replies: Vec<Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(replies.len(), 3);
+        for (reply, in_reply_to) in replies[..2].iter().zip([1, 2]) {
+            assert_eq!(reply["body"]["type"], "error");
+            assert_eq!(reply["body"]["code"], 12);
+            assert_eq!(reply["body"]["in_reply_to"], in_reply_to);
+        }
+        assert_eq!(replies[2]["body"]["type"], "init_ok");
+        assert_eq!(replies[2]["body"]["in_reply_to"], 3);
+    }
+
+    #[test]
+    fn handshake_keeps_a_bounded_number_of_early_lines() {
+        let (output, writer) = Output::start(Buffer::default());
+        let echo = r#"{"src":"c0","dest":"n0","body":{"type":"echo","msg_id":1}}"#;
+        let init = r#"{"src":"c0","dest":"n0","body":{"type":"init","msg_id":2,"node_id":"n0","node_ids":["n0"]}}"#;
+        let mut lines = ["not json", echo]
+            .into_iter()
+            .cycle()
+            .take(MAX_EARLY_MESSAGES * 2)
+            .chain([init])
+            .map(|line| Ok(line.to_string()));
+
+        let (_, early) = Node::handshake(&mut lines, &output, &MsgIds::default()).unwrap();
+        drop(output);
+        writer.join().unwrap().unwrap();
+
+        assert_eq!(early.len(), MAX_EARLY_MESSAGES);
+    }
+
+    #[test]
+    fn owners_follow_the_order_of_node_ids() {
+        let node = Node::new(
+            "n1".to_string(),
+            vec!["n2".into(), "n0".into(), "n1".into()],
+        )
+        .unwrap();
+
+        let owners: Vec<&str> = (0..4).map(|key| node.owner(&key.to_string())).collect();
+        assert_eq!(owners, ["n0", "n1", "n2", "n0"]);
+
+        assert_eq!(node.owner("k"), node.owner("k"));
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::Context as _;
+use serde::Serialize;
+use std::{
+    fmt,
+    io::{self, BufWriter, Write},
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use crate::message::Message;
+
+/// How many lines can wait to be written before senders start blocking.
+const CAPACITY: usize = 1024;
+
+/// Returned when a line is sent after the writer has stopped, e.g. because Maelstrom closed
+/// stdout. The runtime treats it as a request to shut down.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Output is closed")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+/// Sending side of the output pipeline.
+///
+/// Every message of the node goes through a single writer thread, so lines are never
+/// interleaved. Lines are flushed in batches, whenever the writer has nothing left to write.
+#[derive(Debug, Clone)]
+pub(crate) struct Output {
+    tx: mpsc::SyncSender<String>,
+}
+
+impl Output {
+    /// Starts the writer thread on `out`, usually stdout. It stops once every `Output` is
+    /// dropped, after flushing the pending lines.
+    pub fn start<W: Write + Send + 'static>(out: W) -> (Self, JoinHandle<io::Result<()>>) {
+        let (tx, rx) = mpsc::sync_channel::<String>(CAPACITY);
+
+        let handle = thread::spawn(move || {
+            let mut out = BufWriter::new(out);
+
+            while let Ok(line) = rx.recv() {
+                writeln!(out, "{}", line)?;
+
+                while let Ok(line) = rx.try_recv() {
+                    writeln!(out, "{}", line)?;
+                }
+
+                out.flush()?;
+            }
+
+            Ok(())
+        });
+
+        (Self { tx }, handle)
+    }
+
+    pub fn write<P: Serialize>(&self, msg: &Message<P>) -> anyhow::Result<()> {
+        let line = serde_json::to_string(msg).context("Message serialization error")?;
+
+        self.write_line(line)
+    }
+
+    /// Queues `line`, waiting for room when the writer is behind.
+    ///
+    /// On a tokio worker the wait goes through `block_in_place`, so the other tasks of the
+    /// worker keep running while the writer catches up.
+    pub fn write_line(&self, line: String) -> anyhow::Result<()> {
+        let line = match self.tx.try_send(line) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::TrySendError::Full(line)) => line,
+            Err(mpsc::TrySendError::Disconnected(_)) => return Err(Closed.into()),
+        };
+
+        #[cfg(feature = "async")]
+        if let Ok(handle) = tokio::runtime::Handle::try_current()
+            && handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread
+        {
+            return tokio::task::block_in_place(|| self.tx.send(line)).map_err(|_| Closed.into());
+        }
+
+        self.tx.send(line).map_err(|_| Closed.into())
+    }
+}
+
+/// Waits for the writer to flush what is left once the runtime has stopped with `result`.
+///
+/// A closed output is a normal end: Maelstrom is not listening anymore.
+pub(crate) fn finish(
+    result: anyhow::Result<()>,
+    writer: JoinHandle<io::Result<()>>,
+) -> anyhow::Result<()> {
+    let flushed = writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("Writer thread panicked"))?;
+
+    match result {
+        Err(err) if err.is::<Closed>() => Ok(()),
+        Err(err) => Err(err),
+        Ok(()) => match flushed {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            flushed => flushed.context("Error when writing the output"),
+        },
+    }
+}
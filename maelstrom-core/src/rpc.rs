@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+/// Settings of a request sent with [`Context::rpc`](crate::Context::rpc).
+#[derive(Debug, Clone, Copy)]
+pub struct RpcOptions {
+    /// How long to wait for the reply of each attempt.
+    pub timeout: Duration,
+    /// How many times the request is sent again after a timeout before giving up.
+    pub retries: u32,
+}
+
+impl Default for RpcOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            retries: 0,
+        }
+    }
+}
@@ -0,0 +1,855 @@
+use anyhow::Context as _;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    debug, error,
+    error::{Error, ErrorCode, ErrorPayload},
+    ids::MsgIds,
+    input,
+    log::Logger,
+    message::{self, Body, Malformed, Message},
+    node::Node,
+    output::{self, Output},
+    rpc::RpcOptions,
+    timer::Timers,
+    trace, warn,
+};
+
+/// Workload logic of a node.
+///
+/// The init handshake is already done by the time a handler is built, so implementations only
+/// deal with the messages of their workload.
+pub trait Handler: Sized + 'static {
+    type Payload: Serialize + DeserializeOwned + Send + 'static;
+
+    /// Builds the handler once the node is initialised. Periodic tasks are registered here.
+    fn init(ctx: &mut Context<Self>) -> anyhow::Result<Self>;
+
+    /// Handles a request, returning the payload of the reply if there is one.
+    ///
+    /// A returned [`Error`] is sent back to the requester as an `error` message.
+    fn handle(
+        &mut self,
+        ctx: &mut Context<Self>,
+        req: Message<Self::Payload>,
+    ) -> anyhow::Result<Option<Self::Payload>>;
+
+    /// Handles a message whose type is not part of `Payload`, with its body as raw JSON.
+    ///
+    /// By default it is answered with a `not-supported` error.
+    fn handle_unknown(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        req: Message<Value>,
+    ) -> anyhow::Result<Option<Self::Payload>> {
+        Err(Error::not_supported(format!(
+            "Message type {} not supported",
+            req.kind().unwrap_or("<none>")
+        ))
+        .into())
+    }
+}
+
+type TaskFn<H> = Box<dyn FnMut(&mut H, &mut Context<H>) -> anyhow::Result<()>>;
+
+struct PeriodicTask<H: Handler> {
+    name: &'static str,
+    period: Duration,
+    run: TaskFn<H>,
+}
+
+type RpcCallback<H> = Box<
+    dyn FnOnce(
+        &mut H,
+        &mut Context<H>,
+        Result<Message<<H as Handler>::Payload>, Error>,
+    ) -> anyhow::Result<()>,
+>;
+
+/// A request waiting for its reply.
+struct PendingCall<H: Handler> {
+    dest: String,
+    /// The serialized request, kept to send it again on retries.
+    line: String,
+    options: RpcOptions,
+    attempts: u32,
+    callback: RpcCallback<H>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Timer {
+    /// The periodic task with the given index is due.
+    Task(usize),
+    /// The attempt of the request with the given `msg_id` timed out.
+    Rpc(u32),
+}
+
+#[derive(Debug)]
+enum Event {
+    /// A message arrived on stdin. Its payload is only decoded once we know whether it answers
+    /// one of our requests.
+    Message(Message<Value>),
+    /// A line of stdin could not be parsed as a message.
+    Malformed(Malformed),
+    Timer(Timer),
+    /// Stdin was closed, or could not be read.
+    Shutdown(anyhow::Result<()>),
+}
+
+/// Gives handlers access to the node identity and to the runtime services.
+pub struct Context<H: Handler> {
+    node: Node,
+    output: Output,
+    timers: Timers<Timer>,
+    registered: Vec<PeriodicTask<H>>,
+    msg_ids: MsgIds,
+    pending: HashMap<u32, PendingCall<H>>,
+    /// Logger tagged with the event being handled.
+    logger: Logger,
+}
+
+impl<H: Handler> Context<H> {
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// Logger tagged with the message, reply or task being handled.
+    pub fn log(&self) -> &Logger {
+        &self.logger
+    }
+
+    /// Allocator of the msg_id of every message this node sends.
+    pub fn msg_ids(&self) -> &MsgIds {
+        &self.msg_ids
+    }
+
+    /// Sends a message to `dest` without waiting for any reply.
+    pub fn send(&self, dest: impl Into<String>, payload: H::Payload) -> anyhow::Result<()> {
+        let body = Body::request(self.msg_ids.next(), payload);
+
+        self.output
+            .write(&Message::new(self.node.node_id.clone(), dest, body))
+    }
+
+    /// Answers the request `in_reply_to` sent by `dest`.
+    ///
+    /// Replies returned by [`Handler::handle`] are sent by the runtime, this is for requests
+    /// that are answered later, e.g. once an RPC of our own completes.
+    pub fn reply(
+        &self,
+        dest: impl Into<String>,
+        in_reply_to: Option<u32>,
+        payload: H::Payload,
+    ) -> anyhow::Result<()> {
+        self.write_reply(dest, in_reply_to, payload)
+    }
+
+    /// Answers the request `in_reply_to` sent by `dest` with an `error` message, like
+    /// [`Context::reply`] does with a payload.
+    pub fn reply_error(
+        &self,
+        dest: impl Into<String>,
+        in_reply_to: Option<u32>,
+        error: Error,
+    ) -> anyhow::Result<()> {
+        self.write_reply(dest, in_reply_to, ErrorPayload::from(error))
+    }
+
+    fn write_reply<P: Serialize>(
+        &self,
+        dest: impl Into<String>,
+        in_reply_to: Option<u32>,
+        payload: P,
+    ) -> anyhow::Result<()> {
+        let body = Body::reply(self.msg_ids.next(), in_reply_to, payload);
+
+        self.output
+            .write(&Message::new(self.node.node_id.clone(), dest, body))
+    }
+
+    /// Sends a request to `dest` and runs `callback` on the event loop once the matching reply
+    /// arrives, or once every attempt has timed out.
+    ///
+    /// An `error` reply, or the timeout of the last attempt, reaches the callback as an [`Error`].
+    /// A callback that fails is logged, the node keeps going.
+    ///
+    /// Returns the `msg_id` given to the request.
+    pub fn rpc<F>(
+        &mut self,
+        dest: impl Into<String>,
+        payload: H::Payload,
+        options: RpcOptions,
+        callback: F,
+    ) -> anyhow::Result<u32>
+    where
+        F: FnOnce(
+                &mut H,
+                &mut Context<H>,
+                Result<Message<H::Payload>, Error>,
+            ) -> anyhow::Result<()>
+            + 'static,
+    {
+        let msg_id = self.msg_ids.next();
+        let dest = dest.into();
+        let msg = Message::new(
+            self.node.node_id.clone(),
+            dest.clone(),
+            Body::request(msg_id, payload),
+        );
+        let line = serde_json::to_string(&msg).context("Message serialization error")?;
+
+        self.output.write_line(line.clone())?;
+
+        self.timers
+            .schedule(Instant::now() + options.timeout, Timer::Rpc(msg_id));
+        self.pending.insert(
+            msg_id,
+            PendingCall {
+                dest,
+                line,
+                options,
+                attempts: 1,
+                callback: Box::new(callback),
+            },
+        );
+
+        Ok(msg_id)
+    }
+
+    /// Registers a task that runs every `period` on the event loop, e.g. gossip, anti-entropy
+    /// or heartbeats. The first run happens one period after the registration.
+    ///
+    /// A run that fails is logged, it does not stop the following runs.
+    pub fn every<F>(&mut self, name: &'static str, period: Duration, task: F)
+    where
+        F: FnMut(&mut H, &mut Context<H>) -> anyhow::Result<()> + 'static,
+    {
+        self.registered.push(PeriodicTask {
+            name,
+            period,
+            run: Box::new(task),
+        });
+    }
+}
+
+/// Event loop of a node.
+///
+/// It owns the stdin reader, the output and the timers, and dispatches every event to the
+/// handler from a single thread, so handlers never need to lock their own state.
+struct Runtime<H: Handler> {
+    handler: H,
+    /// Logger of the node, without the tags of any event.
+    logger: Logger,
+    ctx: Context<H>,
+    tasks: Vec<PeriodicTask<H>>,
+    events: mpsc::Receiver<Event>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl<H: Handler> Runtime<H> {
+    fn new<I, W>(mut input: I, out: W) -> anyhow::Result<Self>
+    where
+        I: Iterator<Item = io::Result<String>> + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let (output, writer) = Output::start(out);
+        let msg_ids = MsgIds::default();
+        let (node, early) = Node::handshake(&mut input, &output, &msg_ids)?;
+        let (tx, rx) = mpsc::channel::<Event>();
+
+        let logger = Logger::new(&node.node_id);
+        if !early.is_empty() {
+            debug!(
+                logger,
+                "Replaying {} messages received before init",
+                early.len()
+            );
+        }
+
+        let timers_tx = tx.clone();
+        let timers = Timers::start(move |timer| timers_tx.send(Event::Timer(timer)).is_ok());
+
+        thread::spawn(move || {
+            let result = read_lines(&tx, early.into_iter().map(Ok).chain(input));
+
+            // The receiver is only gone once the event loop has stopped.
+            let _ = tx.send(Event::Shutdown(result));
+        });
+
+        let mut ctx = Context {
+            node,
+            output,
+            timers,
+            registered: Vec::new(),
+            msg_ids,
+            pending: HashMap::new(),
+            logger: logger.clone(),
+        };
+        let handler = H::init(&mut ctx)?;
+
+        let mut runtime = Self {
+            handler,
+            logger,
+            ctx,
+            tasks: Vec::new(),
+            events: rx,
+            writer,
+        };
+        runtime.schedule_registered_tasks();
+
+        Ok(runtime)
+    }
+
+    fn run(mut self) -> anyhow::Result<()> {
+        let result = self.event_loop();
+
+        // Dropping the context drops the last sender of the output, which lets the writer flush
+        // what is left and stop.
+        let Runtime { ctx, writer, .. } = self;
+        drop(ctx);
+
+        output::finish(result, writer)
+    }
+
+    fn event_loop(&mut self) -> anyhow::Result<()> {
+        while let Ok(event) = self.events.recv() {
+            match event {
+                Event::Message(msg) => self.dispatch(msg)?,
+                Event::Malformed(malformed) => self.reject(malformed)?,
+                Event::Timer(Timer::Task(index)) => {
+                    let task = &mut self.tasks[index];
+
+                    self.ctx.logger = self.logger.with("task", task.name);
+
+                    if let Err(err) = (task.run)(&mut self.handler, &mut self.ctx) {
+                        error!(self.ctx.logger, "Periodic task failed: {:#}", err);
+                    }
+
+                    self.ctx
+                        .timers
+                        .schedule(Instant::now() + task.period, Timer::Task(index));
+                }
+                Event::Timer(Timer::Rpc(msg_id)) => self.timeout(msg_id)?,
+                Event::Shutdown(result) => return result,
+            }
+
+            self.ctx.logger = self.logger.clone();
+            self.schedule_registered_tasks();
+        }
+
+        Ok(())
+    }
+
+    /// Hands a reply to the callback of its request, and any other message to the handler.
+    fn dispatch(&mut self, msg: Message<Value>) -> anyhow::Result<()> {
+        let is_error = msg.kind() == Some("error");
+
+        self.ctx.logger = self.logger.for_message(&msg);
+        trace!(self.ctx.logger, "Received message");
+
+        if let Some(call) = msg
+            .body
+            .in_reply_to
+            .and_then(|in_reply_to| self.ctx.pending.remove(&in_reply_to))
+        {
+            let reply = if is_error {
+                match msg.decode::<ErrorPayload>() {
+                    Ok(msg) => Err(msg.body.payload.into()),
+                    Err(error) => Err(error),
+                }
+            } else {
+                msg.decode()
+            };
+
+            self.complete(call, reply);
+
+            return Ok(());
+        }
+
+        // Errors and replies that nobody waits for anymore, e.g. because their request timed out,
+        // are dropped, answering them would only start a ping-pong of errors between nodes.
+        if is_error || msg.body.in_reply_to.is_some() {
+            debug!(self.ctx.logger, "Dropping reply that nobody waits for");
+
+            return Ok(());
+        }
+
+        let (src, msg_id) = (msg.src.clone(), msg.body.msg_id);
+
+        let result = if msg.kind() == Some("init") {
+            let ctx = &self.ctx;
+
+            ctx.node
+                .reinit(&msg, &ctx.output, &ctx.msg_ids, &ctx.logger)
+                .map(|_| None)
+        } else {
+            match msg.decode::<H::Payload>() {
+                Ok(req) => self.handler.handle(&mut self.ctx, req),
+                Err(error) if error.code == ErrorCode::NotSupported => {
+                    self.handler.handle_unknown(&mut self.ctx, msg)
+                }
+                Err(error) => Err(error.into()),
+            }
+        };
+
+        match result {
+            Ok(Some(payload)) => self.ctx.write_reply(src, msg_id, payload),
+            Ok(None) => Ok(()),
+            Err(err) => {
+                let error = Error::from(&err);
+
+                log_failure(&self.ctx.logger, &err, &error);
+
+                // Without a msg_id the sender does not expect any reply, not even an error.
+                match msg_id {
+                    Some(_) => self.ctx.write_reply(src, msg_id, ErrorPayload::from(error)),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Logs a line that is not a valid message, answering it with `malformed-request` when we
+    /// know who sent it and which request it was.
+    fn reject(&mut self, malformed: Malformed) -> anyhow::Result<()> {
+        let logger = match &malformed.src {
+            Some(src) => self.logger.with("peer", src),
+            None => self.logger.clone(),
+        };
+
+        warn!(logger, "Dropping malformed message: {}", malformed.reason);
+
+        let (Some(src), Some(msg_id)) = (malformed.src, malformed.msg_id) else {
+            return Ok(());
+        };
+
+        let error = Error::new(ErrorCode::MalformedRequest, malformed.reason);
+
+        self.ctx
+            .write_reply(src, Some(msg_id), ErrorPayload::from(error))
+    }
+
+    /// Sends the request again if it has retries left, otherwise fails its callback.
+    fn timeout(&mut self, msg_id: u32) -> anyhow::Result<()> {
+        // The reply may have arrived while the timer was firing.
+        let Some(mut call) = self.ctx.pending.remove(&msg_id) else {
+            return Ok(());
+        };
+
+        self.ctx.logger = self
+            .logger
+            .with("in_reply_to", msg_id)
+            .with("peer", &call.dest);
+
+        if call.attempts <= call.options.retries {
+            call.attempts += 1;
+
+            debug!(
+                self.ctx.logger,
+                "Request timed out, sending attempt {}", call.attempts
+            );
+
+            self.ctx.output.write_line(call.line.clone())?;
+
+            self.ctx
+                .timers
+                .schedule(Instant::now() + call.options.timeout, Timer::Rpc(msg_id));
+            self.ctx.pending.insert(msg_id, call);
+
+            return Ok(());
+        }
+
+        let error = Error::new(
+            ErrorCode::Timeout,
+            format!(
+                "Request to {} timed out after {} attempt(s)",
+                call.dest, call.attempts
+            ),
+        );
+
+        debug!(self.ctx.logger, "{}", error.text);
+
+        self.complete(call, Err(error));
+
+        Ok(())
+    }
+
+    /// Runs the callback of a request. A callback that fails is logged, it has no request to
+    /// answer and must not stop the node.
+    fn complete(&mut self, call: PendingCall<H>, reply: Result<Message<H::Payload>, Error>) {
+        if let Err(err) = (call.callback)(&mut self.handler, &mut self.ctx, reply) {
+            error!(
+                self.ctx.logger,
+                "Callback of a request to {} failed: {:#}", call.dest, err
+            );
+        }
+    }
+
+    fn schedule_registered_tasks(&mut self) {
+        for task in self.ctx.registered.drain(..) {
+            let index = self.tasks.len();
+
+            self.ctx
+                .timers
+                .schedule(Instant::now() + task.period, Timer::Task(index));
+            self.tasks.push(task);
+        }
+    }
+}
+
+fn read_lines(
+    tx: &mpsc::Sender<Event>,
+    lines: impl Iterator<Item = io::Result<String>>,
+) -> anyhow::Result<()> {
+    for line in lines {
+        let content = line?;
+
+        if content.trim().is_empty() {
+            continue;
+        }
+
+        let event = match message::parse(&content) {
+            Ok(msg) => Event::Message(msg),
+            Err(malformed) => Event::Malformed(malformed),
+        };
+
+        // The event loop has already stopped, so there is nobody left to handle the message.
+        if tx.send(event).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Logs why a message was answered with `error`. Maelstrom errors are part of the normal life of
+/// a workload, anything else is a bug of the handler.
+pub(crate) fn log_failure(logger: &Logger, err: &anyhow::Error, error: &Error) {
+    if err.is::<Error>() {
+        debug!(logger, "Answering with error {}", error);
+    } else {
+        error!(logger, "Handler failed: {:#}", err);
+    }
+}
+
+/// Runs the handler `H` until stdin is closed.
+pub fn run<H: Handler>() -> anyhow::Result<()> {
+    run_with::<H, _, _>(input::stdin(), io::stdout())
+}
+
+/// Like [`run`], reading the messages from `input` and writing them to `out` instead of stdin
+/// and stdout, e.g. to run a node in the cluster of the `harness` feature.
+pub fn run_with<H, I, W>(input: I, out: W) -> anyhow::Result<()>
+where
+    H: Handler,
+    I: Iterator<Item = io::Result<String>> + Send + 'static,
+    W: Write + Send + 'static,
+{
+    Runtime::<H>::new(input, out)?.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Payload {
+        Echo {
+            echo: String,
+        },
+        EchoOk {
+            echo: String,
+        },
+        /// Pings n1, answering with the outcome once the ping completes.
+        Call {
+            retries: u32,
+        },
+        CallOk {
+            code: Option<ErrorCode>,
+        },
+        Ping,
+        PingOk,
+        /// Starts sending a tock to c0 every few milliseconds.
+        Start,
+        StartOk,
+        Tock,
+        /// Starts a periodic task that fails after each tock, and pings n1 with a callback that
+        /// fails too.
+        Fail,
+        FailOk,
+    }
+
+    struct TestHandler;
+
+    impl Handler for TestHandler {
+        type Payload = Payload;
+
+        fn init(_ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+            Ok(Self)
+        }
+
+        fn handle(
+            &mut self,
+            ctx: &mut Context<Self>,
+            req: Message<Payload>,
+        ) -> anyhow::Result<Option<Payload>> {
+            match req.body.payload {
+                Payload::Echo { echo } => Ok(Some(Payload::EchoOk { echo })),
+                Payload::Call { retries } => {
+                    let options = RpcOptions {
+                        timeout: Duration::from_millis(50),
+                        retries,
+                    };
+                    let (src, msg_id) = (req.src, req.body.msg_id);
+
+                    ctx.rpc("n1", Payload::Ping, options, move |_, ctx, reply| {
+                        let code = reply.err().map(|error| error.code);
+
+                        ctx.reply(src, msg_id, Payload::CallOk { code })
+                    })?;
+
+                    Ok(None)
+                }
+                Payload::Start => {
+                    ctx.every("tock", Duration::from_millis(10), |_, ctx| {
+                        ctx.send("c0", Payload::Tock)
+                    });
+
+                    Ok(Some(Payload::StartOk))
+                }
+                Payload::Fail => {
+                    ctx.every("fail", Duration::from_millis(10), |_, ctx| {
+                        ctx.send("c0", Payload::Tock)?;
+
+                        anyhow::bail!("Task failed")
+                    });
+                    ctx.rpc("n1", Payload::Ping, RpcOptions::default(), |_, _, _| {
+                        anyhow::bail!("Callback failed")
+                    })?;
+
+                    Ok(Some(Payload::FailOk))
+                }
+                payload => Err(Error::not_supported(format!("{:?}", payload)).into()),
+            }
+        }
+    }
+
+    /// Output that hands every line written by the node to the test.
+    struct Lines {
+        tx: mpsc::Sender<String>,
+        partial: Vec<u8>,
+    }
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.partial.extend_from_slice(buf);
+
+            while let Some(end) = self.partial.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.partial.drain(..=end).collect();
+                let _ = self
+                    .tx
+                    .send(String::from_utf8_lossy(&line).trim().to_string());
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A node running [`TestHandler`] as n0 on in-memory input and output.
+    struct TestNode {
+        input: mpsc::Sender<String>,
+        output: mpsc::Receiver<String>,
+        runtime: JoinHandle<anyhow::Result<()>>,
+    }
+
+    impl TestNode {
+        fn start() -> Self {
+            let (input, rx) = mpsc::channel::<String>();
+            let (tx, output) = mpsc::channel();
+            let lines = Lines {
+                tx,
+                partial: Vec::new(),
+            };
+
+            let runtime =
+                thread::spawn(move || run_with::<TestHandler, _, _>(rx.into_iter().map(Ok), lines));
+
+            let node = Self {
+                input,
+                output,
+                runtime,
+            };
+            node.send(
+                "c0",
+                json!({"type": "init", "msg_id": 1, "node_id": "n0", "node_ids": ["n0", "n1"]}),
+            );
+            assert_eq!(node.recv()["body"]["type"], "init_ok");
+
+            node
+        }
+
+        fn send(&self, src: &str, body: Value) {
+            let msg = json!({"src": src, "dest": "n0", "body": body});
+
+            self.input.send(msg.to_string()).unwrap();
+        }
+
+        fn recv(&self) -> Value {
+            let line = self
+                .output
+                .recv_timeout(Duration::from_secs(2))
+                .expect("No message from the node");
+
+            serde_json::from_str(&line).unwrap()
+        }
+
+        fn stop(self) {
+            drop(self.input);
+
+            self.runtime.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn answers_requests_and_drops_unexpected_replies() {
+        let node = TestNode::start();
+
+        node.send("c0", json!({"type": "echo", "msg_id": 2, "echo": "hello"}));
+        let reply = node.recv();
+        assert_eq!(reply["dest"], "c0");
+        assert_eq!(reply["body"]["type"], "echo_ok");
+        assert_eq!(reply["body"]["echo"], "hello");
+        assert_eq!(reply["body"]["in_reply_to"], 2);
+
+        node.send("c0", json!({"type": "unknown", "msg_id": 3}));
+        let reply = node.recv();
+        assert_eq!(reply["body"]["type"], "error");
+        assert_eq!(reply["body"]["code"], 10);
+        assert_eq!(reply["body"]["in_reply_to"], 3);
+
+        // A reply to nothing we sent, with a msg_id like every reply, gets no answer.
+        node.send(
+            "n1",
+            json!({"type": "ping_ok", "msg_id": 4, "in_reply_to": 99}),
+        );
+        node.send("c0", json!({"type": "echo", "msg_id": 5, "echo": "after"}));
+        assert_eq!(node.recv()["body"]["in_reply_to"], 5);
+
+        node.stop();
+    }
+
+    #[test]
+    fn rpc_hands_the_reply_to_its_callback() {
+        let node = TestNode::start();
+
+        node.send("c0", json!({"type": "call", "msg_id": 2, "retries": 0}));
+        let ping = node.recv();
+        assert_eq!(ping["dest"], "n1");
+        assert_eq!(ping["body"]["type"], "ping");
+
+        node.send(
+            "n1",
+            json!({"type": "ping_ok", "msg_id": 1, "in_reply_to": ping["body"]["msg_id"]}),
+        );
+        let reply = node.recv();
+        assert_eq!(reply["body"]["type"], "call_ok");
+        assert_eq!(reply["body"]["code"], Value::Null);
+        assert_eq!(reply["body"]["in_reply_to"], 2);
+
+        node.send("c0", json!({"type": "call", "msg_id": 3, "retries": 0}));
+        let ping = node.recv();
+        node.send(
+            "n1",
+            json!({"type": "error", "msg_id": 2, "in_reply_to": ping["body"]["msg_id"], "code": 11}),
+        );
+        assert_eq!(node.recv()["body"]["code"], 11);
+
+        node.stop();
+    }
+
+    #[test]
+    fn rpc_retries_then_times_out() {
+        let node = TestNode::start();
+
+        node.send("c0", json!({"type": "call", "msg_id": 2, "retries": 1}));
+        let first = node.recv();
+        let retry = node.recv();
+        assert_eq!(first, retry);
+
+        let reply = node.recv();
+        assert_eq!(reply["body"]["type"], "call_ok");
+        assert_eq!(reply["body"]["code"], 0);
+
+        // The reply arrives once the callback already ran, it must not run again.
+        node.send(
+            "n1",
+            json!({"type": "ping_ok", "msg_id": 1, "in_reply_to": first["body"]["msg_id"]}),
+        );
+        node.send("c0", json!({"type": "echo", "msg_id": 3, "echo": "after"}));
+        assert_eq!(node.recv()["body"]["in_reply_to"], 3);
+
+        node.stop();
+    }
+
+    #[test]
+    fn runs_periodic_tasks() {
+        let node = TestNode::start();
+
+        node.send("c0", json!({"type": "start", "msg_id": 2}));
+        assert_eq!(node.recv()["body"]["type"], "start_ok");
+
+        for _ in 0..3 {
+            let tock = node.recv();
+            assert_eq!(tock["dest"], "c0");
+            assert_eq!(tock["body"]["type"], "tock");
+        }
+
+        node.stop();
+    }
+
+    #[test]
+    fn failing_tasks_and_callbacks_do_not_stop_the_node() {
+        let node = TestNode::start();
+
+        node.send("c0", json!({"type": "fail", "msg_id": 2}));
+        let ping = node.recv();
+        assert_eq!(ping["body"]["type"], "ping");
+        assert_eq!(node.recv()["body"]["type"], "fail_ok");
+
+        // The task keeps running after it failed.
+        for _ in 0..2 {
+            assert_eq!(node.recv()["body"]["type"], "tock");
+        }
+
+        node.send(
+            "n1",
+            json!({"type": "ping_ok", "msg_id": 1, "in_reply_to": ping["body"]["msg_id"]}),
+        );
+        node.send("c0", json!({"type": "echo", "msg_id": 3, "echo": "after"}));
+
+        let reply = (0..)
+            .map(|_| node.recv())
+            .find(|msg| msg["body"]["type"] != "tock")
+            .unwrap();
+        assert_eq!(reply["body"]["type"], "echo_ok");
+        assert_eq!(reply["body"]["in_reply_to"], 3);
+
+        node.stop();
+    }
+}
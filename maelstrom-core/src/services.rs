@@ -0,0 +1,18 @@
+//! Messages of the services Maelstrom runs next to the nodes of a test, e.g. `lin-kv`, and their
+//! clients.
+//!
+//! Clients are built on the async runtime, each operation being a request to the service node
+//! that is awaited until the service answers.
+mod kv;
+mod tso;
+
+#[cfg(feature = "async")]
+pub use kv::Kv;
+pub use kv::{KvError, KvPayload};
+#[cfg(feature = "harness")]
+pub(crate) use kv::{LIN_KV, LWW_KV, SEQ_KV};
+#[cfg(feature = "harness")]
+pub(crate) use tso::LIN_TSO;
+#[cfg(feature = "async")]
+pub use tso::Tso;
+pub use tso::TsoPayload;
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+#[cfg(feature = "async")]
+use crate::async_runtime::Context;
+use crate::error::{Error, ErrorCode};
+
+/// Requests and replies of the key-value services.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KvPayload {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+}
+
+/// Failure of a key-value operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvError {
+    /// The key has never been written, and the operation does not create it.
+    KeyDoesNotExist,
+    /// The current value of the key is not the `from` of a compare-and-set.
+    PreconditionFailed,
+    /// Any other failure, e.g. a timeout or a value that does not decode.
+    Other(Error),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::KeyDoesNotExist => write!(f, "Key does not exist"),
+            KvError::PreconditionFailed => write!(f, "Precondition failed"),
+            KvError::Other(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+impl From<Error> for KvError {
+    fn from(error: Error) -> Self {
+        match error.code {
+            ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist,
+            ErrorCode::PreconditionFailed => KvError::PreconditionFailed,
+            _ => KvError::Other(error),
+        }
+    }
+}
+
+/// Lets a handler answer its own request with the failure of the service.
+impl From<KvError> for Error {
+    fn from(error: KvError) -> Self {
+        match error {
+            KvError::KeyDoesNotExist => {
+                Error::new(ErrorCode::KeyDoesNotExist, "Key does not exist")
+            }
+            KvError::PreconditionFailed => {
+                Error::new(ErrorCode::PreconditionFailed, "Precondition failed")
+            }
+            KvError::Other(error) => error,
+        }
+    }
+}
+
+/// Names of the key-value services, used by their clients and by the harness.
+#[cfg(any(feature = "async", feature = "harness"))]
+pub(crate) const LIN_KV: &str = "lin-kv";
+#[cfg(any(feature = "async", feature = "harness"))]
+pub(crate) const SEQ_KV: &str = "seq-kv";
+#[cfg(any(feature = "async", feature = "harness"))]
+pub(crate) const LWW_KV: &str = "lww-kv";
+
+/// Client of a Maelstrom key-value service.
+///
+/// Keys and values can be anything that serializes to JSON, as long as every node uses the same
+/// types for the same keys.
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, Copy)]
+pub struct Kv {
+    service: &'static str,
+}
+
+#[cfg(feature = "async")]
+impl Kv {
+    /// Client of `lin-kv`, the linearizable key-value store.
+    pub fn lin() -> Self {
+        Self { service: LIN_KV }
+    }
+
+    /// Client of `seq-kv`, the sequentially consistent key-value store. Its reads may return
+    /// stale values.
+    pub fn seq() -> Self {
+        Self { service: SEQ_KV }
+    }
+
+    /// Client of `lww-kv`, where concurrent writes are resolved by keeping the last one. Reads may
+    /// return stale values and writes may be lost.
+    pub fn lww() -> Self {
+        Self { service: LWW_KV }
+    }
+
+    /// Node id of the service.
+    pub fn service(&self) -> &'static str {
+        self.service
+    }
+
+    pub async fn read<K, V>(&self, ctx: &Context, key: K) -> Result<V, KvError>
+    where
+        K: Serialize,
+        V: serde::de::DeserializeOwned,
+    {
+        let key = to_value(key)?;
+
+        match ctx.call(self.service, KvPayload::Read { key }).await? {
+            KvPayload::ReadOk { value } => Ok(serde_json::from_value(value).map_err(|err| {
+                KvError::Other(Error::new(ErrorCode::MalformedRequest, err.to_string()))
+            })?),
+            payload => Err(unexpected(payload)),
+        }
+    }
+
+    pub async fn write<K, V>(&self, ctx: &Context, key: K, value: V) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let payload = KvPayload::Write {
+            key: to_value(key)?,
+            value: to_value(value)?,
+        };
+
+        match ctx.call(self.service, payload).await? {
+            KvPayload::WriteOk => Ok(()),
+            payload => Err(unexpected(payload)),
+        }
+    }
+
+    /// Sets `key` to `to` if its current value is `from`. With `create_if_not_exists`, a key that
+    /// does not exist yet is created with `to` instead of failing.
+    pub async fn cas<K, V>(
+        &self,
+        ctx: &Context,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let payload = KvPayload::Cas {
+            key: to_value(key)?,
+            from: to_value(from)?,
+            to: to_value(to)?,
+            create_if_not_exists,
+        };
+
+        match ctx.call(self.service, payload).await? {
+            KvPayload::CasOk => Ok(()),
+            payload => Err(unexpected(payload)),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+fn to_value(value: impl Serialize) -> Result<Value, KvError> {
+    serde_json::to_value(value)
+        .map_err(|err| KvError::Other(Error::new(ErrorCode::Crash, err.to_string())))
+}
+
+#[cfg(feature = "async")]
+fn unexpected(payload: KvPayload) -> KvError {
+    KvError::Other(Error::new(
+        ErrorCode::MalformedRequest,
+        format!("Unexpected reply {:?}", payload),
+    ))
+}
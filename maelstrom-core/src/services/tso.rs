@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "async")]
+use crate::{
+    async_runtime::Context,
+    error::{Error, ErrorCode},
+};
+
+/// Name of the timestamp oracle service.
+#[cfg(any(feature = "async", feature = "harness"))]
+pub(crate) const LIN_TSO: &str = "lin-tso";
+
+/// Requests and replies of the timestamp oracle.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TsoPayload {
+    Ts,
+    TsOk { ts: u64 },
+}
+
+/// Client of `lin-tso`, the linearizable timestamp oracle.
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tso;
+
+#[cfg(feature = "async")]
+impl Tso {
+    /// Node id of the service.
+    pub fn service(&self) -> &'static str {
+        LIN_TSO
+    }
+
+    /// Returns a timestamp greater than every timestamp handed out before the request was sent,
+    /// to any node.
+    pub async fn ts(&self, ctx: &Context) -> Result<u64, Error> {
+        match ctx.call(self.service(), TsoPayload::Ts).await? {
+            TsoPayload::TsOk { ts } => Ok(ts),
+            payload => Err(Error::new(
+                ErrorCode::MalformedRequest,
+                format!("Unexpected reply {:?}", payload),
+            )),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+#[derive(Debug)]
+struct Queue<T> {
+    deadlines: BinaryHeap<Reverse<(Instant, T)>>,
+    stopped: bool,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    queue: Mutex<Queue<T>>,
+    wakeup: Condvar,
+}
+
+/// Timer queue driven by a dedicated thread.
+///
+/// Every scheduled key is handed to the `fire` callback once its deadline is reached. The thread
+/// stops when the callback returns `false` or when the queue is dropped.
+#[derive(Debug)]
+pub(crate) struct Timers<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Ord + Send + 'static> Timers<T> {
+    pub fn start<F>(fire: F) -> Self
+    where
+        F: Fn(T) -> bool + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                deadlines: BinaryHeap::new(),
+                stopped: false,
+            }),
+            wakeup: Condvar::new(),
+        });
+
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            let mut queue = thread_shared.queue.lock().expect("Timer queue poisoned");
+
+            loop {
+                if queue.stopped {
+                    break;
+                }
+
+                let now = Instant::now();
+
+                match queue.deadlines.peek() {
+                    Some(Reverse((deadline, _))) if *deadline <= now => {
+                        let Reverse((_, key)) = queue.deadlines.pop().expect("Deadline peeked");
+
+                        // The callback may need to schedule new timers, so it must not run
+                        // while the queue is locked.
+                        drop(queue);
+
+                        if !fire(key) {
+                            break;
+                        }
+
+                        queue = thread_shared.queue.lock().expect("Timer queue poisoned");
+                    }
+                    Some(Reverse((deadline, _))) => {
+                        let timeout = *deadline - now;
+
+                        queue = thread_shared
+                            .wakeup
+                            .wait_timeout(queue, timeout)
+                            .expect("Timer queue poisoned")
+                            .0;
+                    }
+                    None => {
+                        queue = thread_shared
+                            .wakeup
+                            .wait(queue)
+                            .expect("Timer queue poisoned");
+                    }
+                }
+            }
+        });
+
+        Self { shared }
+    }
+
+    pub fn schedule(&self, deadline: Instant, key: T) {
+        let mut queue = self.shared.queue.lock().expect("Timer queue poisoned");
+
+        queue.deadlines.push(Reverse((deadline, key)));
+
+        self.shared.wakeup.notify_one();
+    }
+}
+
+impl<T> Drop for Timers<T> {
+    fn drop(&mut self) {
+        if let Ok(mut queue) = self.shared.queue.lock() {
+            queue.stopped = true;
+        }
+
+        self.shared.wakeup.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn fires_keys_in_deadline_order() {
+        let (tx, rx) = mpsc::channel();
+        let timers = Timers::start(move |key| tx.send(key).is_ok());
+        let now = Instant::now();
+
+        timers.schedule(now + Duration::from_millis(30), 3);
+        timers.schedule(now + Duration::from_millis(10), 1);
+        timers.schedule(now + Duration::from_millis(20), 2);
+
+        let fired: Vec<u32> = rx.iter().take(3).collect();
+
+        assert_eq!(fired, [1, 2, 3]);
+        assert!(Instant::now() >= now + Duration::from_millis(30));
+    }
+
+    #[test]
+    fn stops_once_fire_returns_false() {
+        let (tx, rx) = mpsc::channel();
+        let timers = Timers::start(move |key| {
+            let _ = tx.send(key);
+
+            false
+        });
+        let now = Instant::now();
+
+        timers.schedule(now, 1);
+        timers.schedule(now, 2);
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+        // The thread is gone, along with the sender it owned.
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(1)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+}
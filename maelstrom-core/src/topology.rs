@@ -1,18 +1,19 @@
 use std::collections::HashMap;
 
+/// Shapes in which nodes can be connected to each other for gossiping.
 #[derive(Debug, Clone)]
 pub enum Topology {
-    StarTopology,
-    FullMeshTopology,
-    RingTopology,
+    Star,
+    FullMesh,
+    Ring,
 }
 
 impl Topology {
-    pub fn get_topology(self, node_ids: &Vec<String>) -> HashMap<String, Vec<String>> {
+    pub fn get_topology(self, node_ids: &[String]) -> HashMap<String, Vec<String>> {
         let mut topology: HashMap<String, Vec<String>> = HashMap::new();
 
         match self {
-            Topology::StarTopology => {
+            Topology::Star => {
                 let mut node_ids_iter = node_ids.iter();
 
                 let star_node = node_ids_iter.next().unwrap();
@@ -27,7 +28,7 @@ impl Topology {
 
                 topology
             }
-            Topology::FullMeshTopology => {
+            Topology::FullMesh => {
                 for node_id in node_ids.iter() {
                     topology.insert(
                         node_id.clone(),
@@ -41,7 +42,7 @@ impl Topology {
 
                 topology
             }
-            Topology::RingTopology => {
+            Topology::Ring => {
                 let node_ids_size = node_ids.len();
 
                 for (index, node_id) in node_ids.iter().enumerate() {
@@ -0,0 +1,277 @@
+//! Multi-version store for snapshot isolation.
+//!
+//! Every committed write creates a version of its key, stamped with the timestamp of its
+//! commit. A transaction starts with a [`Snapshot`] of the store, reads the versions committed
+//! before it, and commits its writes only if no other transaction committed a write to the same
+//! keys since its snapshot: the first committer wins. When a commit writes a key, the versions
+//! of that key no snapshot can read anymore are dropped.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash,
+};
+
+/// State of the store a transaction reads, as of a commit timestamp.
+///
+/// A snapshot keeps the versions it can read from being collected, until it is given back to
+/// [`Mvcc::commit`] or [`Mvcc::abort`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    ts: u64,
+}
+
+impl Snapshot {
+    /// Timestamp of the last commit the snapshot sees.
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+}
+
+/// The store as a transaction sees it, through its snapshot.
+#[derive(Debug)]
+pub struct View<'a, K, V> {
+    mvcc: &'a Mvcc<K, V>,
+    snapshot: &'a Snapshot,
+}
+
+impl<'a, K: Eq + Hash + Clone, V> View<'a, K, V> {
+    /// Returns the value `key` had in the snapshot.
+    pub fn read(&self, key: &K) -> Option<&'a V> {
+        self.mvcc.read(self.snapshot, key)
+    }
+}
+
+/// A commit that lost to another transaction, which wrote `key` after the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict<K> {
+    pub key: K,
+    /// Timestamp of the commit that won.
+    pub committed: u64,
+}
+
+impl<K: fmt::Debug> fmt::Display for Conflict<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} was written by the commit {} since the snapshot",
+            self.key, self.committed
+        )
+    }
+}
+
+impl<K: fmt::Debug> std::error::Error for Conflict<K> {}
+
+/// Versions of every key, along with the snapshots in use.
+#[derive(Debug)]
+pub struct Mvcc<K, V> {
+    /// Versions of each key, oldest first, stamped with their commit timestamp.
+    versions: HashMap<K, Vec<(u64, V)>>,
+    /// Timestamp of the last commit.
+    clock: u64,
+    /// Number of snapshots in use, by timestamp.
+    active: BTreeMap<u64, usize>,
+}
+
+impl<K, V> Default for Mvcc<K, V> {
+    fn default() -> Self {
+        Self {
+            versions: HashMap::new(),
+            clock: 0,
+            active: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> Mvcc<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a snapshot of every commit so far.
+    pub fn begin(&mut self) -> Snapshot {
+        *self.active.entry(self.clock).or_default() += 1;
+
+        Snapshot { ts: self.clock }
+    }
+
+    /// Returns the value `key` had in `snapshot`.
+    pub fn read(&self, snapshot: &Snapshot, key: &K) -> Option<&V> {
+        self.versions
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|(ts, _)| *ts <= snapshot.ts)
+            .map(|(_, value)| value)
+    }
+
+    /// Reads the store through `snapshot`.
+    pub fn view<'a>(&'a self, snapshot: &'a Snapshot) -> View<'a, K, V> {
+        View {
+            mvcc: self,
+            snapshot,
+        }
+    }
+
+    /// Commits `writes` on top of `snapshot` and returns the timestamp of the commit, unless
+    /// another transaction committed a write to one of their keys since the snapshot, in which
+    /// case nothing is written.
+    pub fn commit(&mut self, snapshot: Snapshot, writes: Vec<(K, V)>) -> Result<u64, Conflict<K>> {
+        let ts = snapshot.ts;
+        self.release(snapshot);
+
+        self.commit_at(ts, writes)
+    }
+
+    /// Same as [`Mvcc::commit`], for a transaction that read the snapshot at `ts`.
+    ///
+    /// The snapshot may have been taken by another store, as long as both commit the same
+    /// transactions in the same order: their commits then get the same timestamps.
+    pub fn commit_at(&mut self, ts: u64, writes: Vec<(K, V)>) -> Result<u64, Conflict<K>> {
+        let conflict = writes.iter().find_map(|(key, _)| {
+            let (committed, _) = self.versions.get(key)?.last()?;
+
+            (*committed > ts).then(|| Conflict {
+                key: key.clone(),
+                committed: *committed,
+            })
+        });
+
+        if let Some(conflict) = conflict {
+            return Err(conflict);
+        }
+
+        if writes.is_empty() {
+            return Ok(self.clock);
+        }
+
+        self.clock += 1;
+        let watermark = self.low_watermark();
+
+        for (key, value) in writes {
+            let versions = self.versions.entry(key).or_default();
+
+            // A transaction writing a key several times only leaves its last value.
+            match versions.last_mut() {
+                Some((ts, current)) if *ts == self.clock => *current = value,
+                _ => versions.push((self.clock, value)),
+            }
+
+            prune(versions, watermark);
+        }
+
+        Ok(self.clock)
+    }
+
+    /// Gives back a snapshot without committing anything.
+    pub fn abort(&mut self, snapshot: Snapshot) {
+        self.release(snapshot);
+    }
+
+    /// Timestamp below which no snapshot reads anymore: the one of the oldest snapshot in use,
+    /// or of the last commit when there is none.
+    pub fn low_watermark(&self) -> u64 {
+        self.active.keys().next().copied().unwrap_or(self.clock)
+    }
+
+    fn release(&mut self, snapshot: Snapshot) {
+        if let Some(count) = self.active.get_mut(&snapshot.ts) {
+            *count -= 1;
+
+            if *count == 0 {
+                self.active.remove(&snapshot.ts);
+            }
+        }
+    }
+}
+
+/// Keeps the versions after `watermark`, and the one a snapshot at `watermark` reads.
+fn prune<V>(versions: &mut Vec<(u64, V)>, watermark: u64) {
+    let visible = versions.partition_point(|(ts, _)| *ts <= watermark);
+
+    if visible > 1 {
+        versions.drain(..visible - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Commits `writes` on top of a fresh snapshot.
+    fn write(mvcc: &mut Mvcc<u64, u64>, writes: Vec<(u64, u64)>) -> u64 {
+        let snapshot = mvcc.begin();
+
+        mvcc.commit(snapshot, writes).unwrap()
+    }
+
+    #[test]
+    fn a_snapshot_only_reads_commits_before_it() {
+        let mut mvcc = Mvcc::new();
+
+        let before = mvcc.begin();
+        write(&mut mvcc, vec![(1, 10)]);
+        let after = mvcc.begin();
+        write(&mut mvcc, vec![(1, 11), (2, 20)]);
+
+        assert_eq!(mvcc.read(&before, &1), None);
+        assert_eq!(mvcc.read(&after, &1), Some(&10));
+        assert_eq!(mvcc.read(&after, &2), None);
+        let latest = mvcc.begin();
+        assert_eq!(mvcc.read(&latest, &1), Some(&11));
+    }
+
+    #[test]
+    fn the_first_committer_wins() {
+        let mut mvcc = Mvcc::new();
+
+        let first = mvcc.begin();
+        let second = mvcc.begin();
+
+        assert_eq!(mvcc.commit(first, vec![(1, 10)]), Ok(1));
+        assert_eq!(
+            mvcc.commit(second, vec![(2, 20), (1, 11)]),
+            Err(Conflict {
+                key: 1,
+                committed: 1
+            })
+        );
+
+        // Nothing of the losing commit is written.
+        let snapshot = mvcc.begin();
+        assert_eq!(mvcc.read(&snapshot, &1), Some(&10));
+        assert_eq!(mvcc.read(&snapshot, &2), None);
+
+        // Writing other keys than the winner does not conflict.
+        assert_eq!(mvcc.commit_at(0, vec![(2, 20)]), Ok(2));
+    }
+
+    #[test]
+    fn commits_prune_versions_below_the_low_watermark() {
+        let mut mvcc = Mvcc::new();
+        write(&mut mvcc, vec![(1, 10)]);
+
+        let old = mvcc.begin();
+        write(&mut mvcc, vec![(1, 11)]);
+        write(&mut mvcc, vec![(1, 12)]);
+
+        // The old snapshot still reads the version of its time.
+        assert_eq!(mvcc.low_watermark(), 1);
+        assert_eq!(mvcc.versions[&1].len(), 3);
+        assert_eq!(mvcc.read(&old, &1), Some(&10));
+
+        let newer = mvcc.begin();
+        mvcc.abort(old);
+        write(&mut mvcc, vec![(1, 13)]);
+
+        // Only the version the newer snapshot reads and the ones after it are left.
+        assert_eq!(mvcc.low_watermark(), 3);
+        assert_eq!(mvcc.versions[&1], vec![(3, 12), (4, 13)]);
+        assert_eq!(mvcc.read(&newer, &1), Some(&12));
+
+        mvcc.abort(newer);
+        write(&mut mvcc, vec![(1, 14)]);
+
+        assert_eq!(mvcc.versions[&1], vec![(5, 14)]);
+    }
+}
@@ -0,0 +1,761 @@
+//! Raft consensus.
+//!
+//! [`Raft`] is a state machine without any I/O, driven by its caller: [`Raft::tick`] as time
+//! passes, [`Raft::step`] for every message received from another member, and [`Raft::propose`]
+//! for new commands. The messages it wants to send are collected with [`Raft::take_messages`],
+//! and the entries once they are committed with [`Raft::take_committed`], in log order, so that
+//! every member applies the same commands in the same order.
+//!
+//! The log lives in memory and is never compacted.
+//!
+//! [`Replicated`] is the handler that does the I/O for a Maelstrom workload: it drives a
+//! [`Raft`] member from messages and a timer, and runs the committed commands through a
+//! [`StateMachine`].
+mod message;
+mod replicated;
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+pub use message::{Entry, RaftMessage};
+pub use replicated::{Decoded, Replicated, StateMachine};
+
+/// Shortest time a follower waits for its leader before starting an election. The actual
+/// timeout is drawn between this and twice this, so that members rarely run at the same time.
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often a leader sends entries, or heartbeats when it has none, to its followers.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Most entries sent in a single append.
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Debug)]
+enum Role {
+    Follower,
+    Candidate {
+        votes: HashSet<String>,
+    },
+    Leader {
+        /// Index of the next entry to send to each follower.
+        next_index: HashMap<String, u64>,
+        /// Highest index known to be replicated on each follower.
+        match_index: HashMap<String, u64>,
+    },
+}
+
+/// A member of a Raft cluster.
+#[derive(Debug)]
+pub struct Raft<C> {
+    id: String,
+    peers: Vec<String>,
+    /// Members needed to win an election or to commit an entry, this one included.
+    quorum: usize,
+    role: Role,
+    term: u64,
+    voted_for: Option<String>,
+    leader: Option<String>,
+    /// Entry `i` has index `i + 1`, index 0 being before the first entry.
+    log: Vec<Entry<C>>,
+    commit_index: u64,
+    /// Index of the last entry returned by [`Raft::take_committed`].
+    applied: u64,
+    election_deadline: Instant,
+    heartbeat_due: Instant,
+    /// Xorshift state for the election timeouts.
+    rng: u64,
+    outbox: Vec<(String, RaftMessage<C>)>,
+}
+
+impl<C: Clone> Raft<C> {
+    /// Member `id` of a cluster made of `members`, which includes it. `seed` draws the election
+    /// timeouts, and should differ between members.
+    pub fn new(id: impl Into<String>, members: &[String], seed: u64, now: Instant) -> Self {
+        let id = id.into();
+        let peers: Vec<String> = members
+            .iter()
+            .filter(|member| **member != id)
+            .cloned()
+            .collect();
+        let size = peers.len() + 1;
+
+        let mut raft = Self {
+            quorum: size / 2 + 1,
+            id,
+            peers,
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader: None,
+            log: Vec::new(),
+            commit_index: 0,
+            applied: 0,
+            election_deadline: now,
+            heartbeat_due: now,
+            // Xorshift never leaves 0.
+            rng: seed | 1,
+            outbox: Vec::new(),
+        };
+        raft.reset_election_deadline(now);
+
+        raft
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// Leader of the current term, if this member knows it.
+    pub fn leader(&self) -> Option<&str> {
+        self.leader.as_deref()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader { .. })
+    }
+
+    /// Appends `command` to the log if this member leads, and returns the index and term of its
+    /// entry. The command is only committed if that entry still has that term when it is
+    /// returned by [`Raft::take_committed`].
+    ///
+    /// Returns the known leader otherwise.
+    pub fn propose(&mut self, command: C) -> Result<(u64, u64), Option<String>> {
+        if !self.is_leader() {
+            return Err(self.leader.clone());
+        }
+
+        self.log.push(Entry {
+            term: self.term,
+            command: Some(command),
+        });
+        // A cluster of one commits on its own.
+        self.advance_commit();
+
+        Ok((self.last_index(), self.term))
+    }
+
+    /// Lets time pass: starts an election when the leader has been silent for too long, and
+    /// sends entries to the followers when this member leads.
+    pub fn tick(&mut self, now: Instant) {
+        if self.is_leader() {
+            let heartbeat = now >= self.heartbeat_due;
+
+            if heartbeat {
+                self.heartbeat_due = now + HEARTBEAT_INTERVAL;
+            }
+
+            self.replicate(heartbeat);
+        } else if now >= self.election_deadline {
+            self.start_election(now);
+        }
+    }
+
+    /// Handles a message of another member.
+    pub fn step(&mut self, now: Instant, from: &str, msg: RaftMessage<C>) {
+        if msg.term() > self.term {
+            self.become_follower(msg.term(), None);
+        }
+
+        match msg {
+            RaftMessage::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => {
+                let up_to_date = (last_log_term, last_log_index)
+                    >= (self.term_at(self.last_index()), self.last_index());
+                let granted = term == self.term
+                    && self.voted_for.as_deref().is_none_or(|voted| voted == from)
+                    && up_to_date;
+
+                if granted {
+                    self.voted_for = Some(from.to_string());
+                    self.reset_election_deadline(now);
+                }
+
+                self.send(
+                    from,
+                    RaftMessage::RequestVoteOk {
+                        term: self.term,
+                        granted,
+                    },
+                );
+            }
+            RaftMessage::RequestVoteOk { term, granted } => {
+                if let Role::Candidate { votes } = &mut self.role
+                    && term == self.term
+                    && granted
+                {
+                    votes.insert(from.to_string());
+
+                    if votes.len() >= self.quorum {
+                        self.become_leader(now);
+                    }
+                }
+            }
+            RaftMessage::AppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                if term < self.term {
+                    self.send(
+                        from,
+                        RaftMessage::AppendEntriesOk {
+                            term: self.term,
+                            success: false,
+                            match_index: self.last_index(),
+                        },
+                    );
+
+                    return;
+                }
+
+                // A candidate of this term lost to the sender.
+                if !matches!(self.role, Role::Follower) {
+                    self.become_follower(term, self.voted_for.clone());
+                }
+
+                self.leader = Some(from.to_string());
+                self.reset_election_deadline(now);
+
+                let reply = self.append(prev_log_index, prev_log_term, entries, leader_commit);
+                self.send(from, reply);
+            }
+            RaftMessage::AppendEntriesOk {
+                term,
+                success,
+                match_index: matched,
+            } => {
+                let Role::Leader {
+                    next_index,
+                    match_index,
+                } = &mut self.role
+                else {
+                    return;
+                };
+
+                if term != self.term {
+                    return;
+                }
+
+                if success {
+                    let known = match_index.entry(from.to_string()).or_default();
+                    *known = matched.max(*known);
+
+                    let next = next_index.entry(from.to_string()).or_default();
+                    *next = (matched + 1).max(*next);
+
+                    self.advance_commit();
+                } else {
+                    // Entries were sent past the end of the follower's log, or do not match it:
+                    // go back and send them again.
+                    let next = next_index.entry(from.to_string()).or_insert(1);
+                    *next = (*next - 1).min(matched + 1).max(1);
+
+                    self.replicate_to(from);
+                }
+            }
+        }
+    }
+
+    /// Messages to send since the last call, along with their destination.
+    pub fn take_messages(&mut self) -> Vec<(String, RaftMessage<C>)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Entries committed since the last call, along with their index, in log order.
+    pub fn take_committed(&mut self) -> Vec<(u64, Entry<C>)> {
+        let committed = (self.applied + 1..=self.commit_index)
+            .map(|index| (index, self.log[index as usize - 1].clone()))
+            .collect();
+
+        self.applied = self.commit_index;
+
+        committed
+    }
+
+    fn last_index(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    /// Term of the entry at `index`, 0 before the first entry.
+    fn term_at(&self, index: u64) -> u64 {
+        match index {
+            0 => 0,
+            index => self
+                .log
+                .get(index as usize - 1)
+                .map_or(0, |entry| entry.term),
+        }
+    }
+
+    fn send(&mut self, to: &str, msg: RaftMessage<C>) {
+        self.outbox.push((to.to_string(), msg));
+    }
+
+    fn reset_election_deadline(&mut self, now: Instant) {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+
+        let jitter = self.rng % ELECTION_TIMEOUT.as_millis() as u64;
+
+        self.election_deadline = now + ELECTION_TIMEOUT + Duration::from_millis(jitter);
+    }
+
+    fn become_follower(&mut self, term: u64, voted_for: Option<String>) {
+        self.role = Role::Follower;
+        self.term = term;
+        self.voted_for = voted_for;
+        self.leader = None;
+    }
+
+    fn start_election(&mut self, now: Instant) {
+        self.term += 1;
+        self.role = Role::Candidate {
+            votes: HashSet::from([self.id.clone()]),
+        };
+        self.voted_for = Some(self.id.clone());
+        self.leader = None;
+        self.reset_election_deadline(now);
+
+        for peer in self.peers.clone() {
+            self.send(
+                &peer,
+                RaftMessage::RequestVote {
+                    term: self.term,
+                    last_log_index: self.last_index(),
+                    last_log_term: self.term_at(self.last_index()),
+                },
+            );
+        }
+
+        if self.quorum == 1 {
+            self.become_leader(now);
+        }
+    }
+
+    fn become_leader(&mut self, now: Instant) {
+        let next = self.last_index() + 1;
+
+        self.role = Role::Leader {
+            next_index: self.peers.iter().map(|peer| (peer.clone(), next)).collect(),
+            match_index: self.peers.iter().map(|peer| (peer.clone(), 0)).collect(),
+        };
+        self.leader = Some(self.id.clone());
+        self.heartbeat_due = now + HEARTBEAT_INTERVAL;
+
+        // Entries of former terms are only committed along with one of the current term.
+        self.log.push(Entry {
+            term: self.term,
+            command: None,
+        });
+        self.advance_commit();
+        self.replicate(true);
+    }
+
+    /// Sends the entries each follower is missing, or a heartbeat to the followers that have
+    /// them all when `heartbeat` is set.
+    fn replicate(&mut self, heartbeat: bool) {
+        let Role::Leader { next_index, .. } = &self.role else {
+            return;
+        };
+
+        let last_index = self.last_index();
+        let behind: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|peer| {
+                heartbeat
+                    || next_index
+                        .get(*peer)
+                        .is_some_and(|next| *next <= last_index)
+            })
+            .cloned()
+            .collect();
+
+        for peer in behind {
+            self.replicate_to(&peer);
+        }
+    }
+
+    /// Sends the entries `peer` is missing. They are assumed to arrive, so the next ones follow
+    /// right after them, and a failed append makes the leader go back.
+    fn replicate_to(&mut self, peer: &str) {
+        let last_index = self.last_index();
+        let Role::Leader { next_index, .. } = &mut self.role else {
+            return;
+        };
+        let Some(next) = next_index.get_mut(peer) else {
+            return;
+        };
+
+        let prev_log_index = *next - 1;
+        let end = last_index.min(prev_log_index + MAX_ENTRIES as u64);
+        *next = end + 1;
+
+        let msg = RaftMessage::AppendEntries {
+            term: self.term,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index),
+            entries: self.log[prev_log_index as usize..end as usize].to_vec(),
+            leader_commit: self.commit_index,
+        };
+
+        self.send(peer, msg);
+    }
+
+    /// Appends the entries of the leader after `prev_log_index`, if the log matches the leader's
+    /// up to there.
+    fn append(
+        &mut self,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<C>>,
+        leader_commit: u64,
+    ) -> RaftMessage<C> {
+        if prev_log_index > self.last_index() || self.term_at(prev_log_index) != prev_log_term {
+            return RaftMessage::AppendEntriesOk {
+                term: self.term,
+                success: false,
+                match_index: self.last_index().min(prev_log_index.saturating_sub(1)),
+            };
+        }
+
+        let matched = prev_log_index + entries.len() as u64;
+
+        for (index, entry) in (prev_log_index + 1..).zip(entries) {
+            if index <= self.last_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+
+                // A former leader appended entries that were never committed.
+                self.log.truncate(index as usize - 1);
+            }
+
+            self.log.push(entry);
+        }
+
+        self.commit_index = self.commit_index.max(leader_commit.min(matched));
+
+        RaftMessage::AppendEntriesOk {
+            term: self.term,
+            success: true,
+            match_index: matched,
+        }
+    }
+
+    /// Commits the entries of the current term that a quorum has.
+    fn advance_commit(&mut self) {
+        let Role::Leader { match_index, .. } = &self.role else {
+            return;
+        };
+
+        for index in (self.commit_index + 1..=self.last_index()).rev() {
+            if self.term_at(index) != self.term {
+                break;
+            }
+
+            let replicas = 1 + match_index
+                .values()
+                .filter(|known| **known >= index)
+                .count();
+
+            if replicas >= self.quorum {
+                self.commit_index = index;
+
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members() -> Vec<String> {
+        vec!["n0".to_string(), "n1".to_string(), "n2".to_string()]
+    }
+
+    fn member(id: &str, now: Instant) -> Raft<u64> {
+        let seed = members().iter().position(|member| member == id).unwrap() as u64;
+
+        Raft::new(id, &members(), seed, now)
+    }
+
+    /// Lets the election timeout of `raft` pass, and gives it the vote of `voter`.
+    fn elect(raft: &mut Raft<u64>, voter: &str, now: Instant) -> Instant {
+        let now = now + ELECTION_TIMEOUT * 2;
+
+        raft.tick(now);
+        raft.take_messages();
+        raft.step(
+            now,
+            voter,
+            RaftMessage::RequestVoteOk {
+                term: raft.term(),
+                granted: true,
+            },
+        );
+        raft.take_messages();
+
+        now
+    }
+
+    fn entry(term: u64, command: u64) -> Entry<u64> {
+        Entry {
+            term,
+            command: Some(command),
+        }
+    }
+
+    #[test]
+    fn wins_an_election_with_a_quorum() {
+        let now = Instant::now();
+        let mut raft = member("n0", now);
+
+        let now = now + ELECTION_TIMEOUT * 2;
+        raft.tick(now);
+
+        let requests = raft.take_messages();
+        assert_eq!(requests.len(), 2);
+        assert!(
+            requests
+                .iter()
+                .all(|(_, msg)| matches!(msg, RaftMessage::RequestVote { term: 1, .. }))
+        );
+        assert!(!raft.is_leader());
+
+        // A refusal, or a vote of another term, does not count.
+        raft.step(
+            now,
+            "n2",
+            RaftMessage::RequestVoteOk {
+                term: 1,
+                granted: false,
+            },
+        );
+        assert!(!raft.is_leader());
+
+        raft.step(
+            now,
+            "n1",
+            RaftMessage::RequestVoteOk {
+                term: 1,
+                granted: true,
+            },
+        );
+        assert!(raft.is_leader());
+        assert_eq!(raft.leader(), Some("n0"));
+        assert_eq!(raft.term(), 1);
+
+        // The new leader appends an entry of its term right away.
+        let appends = raft.take_messages();
+        assert_eq!(appends.len(), 2);
+        assert!(appends.iter().all(|(_, msg)| matches!(
+            msg,
+            RaftMessage::AppendEntries { entries, .. } if entries.len() == 1
+        )));
+    }
+
+    #[test]
+    fn refuses_a_vote_to_an_out_of_date_log() {
+        let now = Instant::now();
+        let mut raft = member("n1", now);
+
+        raft.step(
+            now,
+            "n0",
+            RaftMessage::AppendEntries {
+                term: 1,
+                prev_log_index: 0,
+                prev_log_term: 0,
+                entries: vec![entry(1, 7)],
+                leader_commit: 0,
+            },
+        );
+        raft.take_messages();
+
+        raft.step(
+            now,
+            "n2",
+            RaftMessage::RequestVote {
+                term: 2,
+                last_log_index: 0,
+                last_log_term: 0,
+            },
+        );
+        assert_eq!(
+            raft.take_messages(),
+            [(
+                "n2".to_string(),
+                RaftMessage::RequestVoteOk {
+                    term: 2,
+                    granted: false
+                }
+            )]
+        );
+
+        raft.step(
+            now,
+            "n2",
+            RaftMessage::RequestVote {
+                term: 3,
+                last_log_index: 1,
+                last_log_term: 1,
+            },
+        );
+        assert_eq!(
+            raft.take_messages(),
+            [(
+                "n2".to_string(),
+                RaftMessage::RequestVoteOk {
+                    term: 3,
+                    granted: true
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn truncates_a_conflicting_suffix() {
+        let now = Instant::now();
+        let mut raft = member("n1", now);
+
+        raft.step(
+            now,
+            "n0",
+            RaftMessage::AppendEntries {
+                term: 1,
+                prev_log_index: 0,
+                prev_log_term: 0,
+                entries: vec![entry(1, 1), entry(1, 2)],
+                leader_commit: 0,
+            },
+        );
+
+        // The leader of term 2 only has the first entry of term 1.
+        raft.step(
+            now,
+            "n2",
+            RaftMessage::AppendEntries {
+                term: 2,
+                prev_log_index: 1,
+                prev_log_term: 1,
+                entries: vec![entry(2, 3)],
+                leader_commit: 2,
+            },
+        );
+
+        assert_eq!(
+            raft.take_messages().last(),
+            Some(&(
+                "n2".to_string(),
+                RaftMessage::AppendEntriesOk {
+                    term: 2,
+                    success: true,
+                    match_index: 2
+                }
+            ))
+        );
+        assert_eq!(raft.take_committed(), [(1, entry(1, 1)), (2, entry(2, 3))]);
+    }
+
+    #[test]
+    fn commits_only_entries_of_the_current_term() {
+        let now = Instant::now();
+        let mut raft = member("n0", now);
+
+        // An entry of term 1 that no follower got.
+        let now = elect(&mut raft, "n1", now);
+        assert_eq!(raft.propose(7), Ok((2, 1)));
+        raft.take_messages();
+
+        // Deposed by a candidate it refuses to vote for, n0 is then elected again in term 3.
+        raft.step(
+            now,
+            "n2",
+            RaftMessage::RequestVote {
+                term: 2,
+                last_log_index: 0,
+                last_log_term: 0,
+            },
+        );
+        let now = elect(&mut raft, "n1", now);
+        assert!(raft.is_leader());
+        assert_eq!(raft.term(), 3);
+
+        // A quorum has the entry of term 1, which is still not committed on its own.
+        let ok = |match_index| RaftMessage::AppendEntriesOk {
+            term: 3,
+            success: true,
+            match_index,
+        };
+        raft.step(now, "n1", ok(2));
+        assert!(raft.take_committed().is_empty());
+
+        // It is once an entry of term 3 is.
+        raft.step(now, "n1", ok(3));
+        let committed = raft.take_committed();
+        assert_eq!(
+            committed
+                .iter()
+                .map(|(index, _)| *index)
+                .collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(committed[1].1, entry(1, 7));
+    }
+
+    #[test]
+    fn a_leader_in_a_minority_never_commits() {
+        let now = Instant::now();
+        let mut raft = member("n0", now);
+        let mut now = elect(&mut raft, "n1", now);
+
+        // Cut off from the others, the leader keeps sending appends nobody answers.
+        assert!(raft.propose(7).is_ok());
+        for _ in 0..50 {
+            now += HEARTBEAT_INTERVAL;
+            raft.tick(now);
+            raft.take_messages();
+        }
+        assert!(raft.is_leader());
+        assert!(raft.take_committed().is_empty());
+
+        // Once it hears from the leader the majority elected, its entries are replaced.
+        raft.step(
+            now,
+            "n1",
+            RaftMessage::AppendEntries {
+                term: 2,
+                prev_log_index: 0,
+                prev_log_term: 0,
+                entries: vec![Entry {
+                    term: 2,
+                    command: None,
+                }],
+                leader_commit: 1,
+            },
+        );
+        assert!(!raft.is_leader());
+        assert_eq!(raft.leader(), Some("n1"));
+        assert_eq!(
+            raft.take_committed(),
+            [(
+                1,
+                Entry {
+                    term: 2,
+                    command: None
+                }
+            )]
+        );
+    }
+}
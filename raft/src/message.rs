@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+/// An entry of the replicated log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry<C> {
+    /// Term of the leader that appended the entry.
+    pub term: u64,
+    /// Command of the entry, or nothing for the entry a new leader appends to commit the entries
+    /// of its predecessors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<C>,
+}
+
+/// Messages exchanged by the members of a Raft cluster.
+///
+/// They are tagged by `raft`, so that a workload can carry them in a variant of its own payload,
+/// itself tagged by `type`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "raft", rename_all = "snake_case")]
+pub enum RaftMessage<C> {
+    RequestVote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    RequestVoteOk {
+        term: u64,
+        granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<C>>,
+        leader_commit: u64,
+    },
+    AppendEntriesOk {
+        term: u64,
+        success: bool,
+        /// Last index known to match the leader on success, and the last index of the follower
+        /// otherwise, to skip back quickly.
+        match_index: u64,
+    },
+}
+
+impl<C> RaftMessage<C> {
+    pub fn term(&self) -> u64 {
+        match self {
+            RaftMessage::RequestVote { term, .. }
+            | RaftMessage::RequestVoteOk { term, .. }
+            | RaftMessage::AppendEntries { term, .. }
+            | RaftMessage::AppendEntriesOk { term, .. } => *term,
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use maelstrom_core::{Context, Error, ErrorCode, Handler, Message, RpcOptions, debug};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{Raft, RaftMessage};
+
+/// How often the Raft state machine is driven forward.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a node waits for the leader to answer a request it forwarded.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What a message of a workload carries.
+#[derive(Debug)]
+pub enum Decoded<C, P> {
+    /// A command a client asks to run.
+    Command(C),
+    /// A message of Raft from another member.
+    Raft(RaftMessage<C>),
+    /// A request answered right away, without going through the log.
+    Reply(P),
+    /// Anything else, which is not supported.
+    Other(P),
+}
+
+/// A deterministic state machine, which every member runs through the same commands in the
+/// same order, so that they all end up in the same state.
+pub trait StateMachine: Default + fmt::Debug + 'static {
+    /// Command appended to the log, e.g. a transaction.
+    type Command: Clone + fmt::Debug + 'static;
+    /// Payload of the messages of the workload, the ones of Raft included.
+    type Payload: Serialize + DeserializeOwned + Send + fmt::Debug + 'static;
+
+    /// Tells the commands of clients and the messages of Raft apart from anything else, or
+    /// answers a request that needs no command.
+    fn decode(&mut self, payload: Self::Payload) -> Decoded<Self::Command, Self::Payload>;
+
+    /// Payload asking the leader to run `command`, to forward it.
+    fn request(command: Self::Command) -> Self::Payload;
+
+    /// Payload carrying a message of Raft.
+    fn raft(msg: RaftMessage<Self::Command>) -> Self::Payload;
+
+    /// Runs a committed command, returning the reply to the client that asked for it.
+    fn apply(&mut self, index: u64, command: Self::Command) -> Result<Self::Payload, Error>;
+}
+
+/// A command proposed by this node, waiting for its entry to be committed.
+#[derive(Debug)]
+struct Proposal {
+    /// Term the entry was appended in. Another term at its index means it was overwritten.
+    term: u64,
+    src: String,
+    msg_id: Option<u32>,
+}
+
+/// Handler replicating a [`StateMachine`] through a Raft log.
+///
+/// Every command is appended to the log by the leader, and runs on every node once it is
+/// committed, in log order, so a command sees every command acknowledged before it was sent.
+///
+/// Other nodes forward commands to the leader. Nodes that cannot reach a majority of the
+/// cluster refuse them, so a replicated state machine is not available under partitions.
+#[derive(Debug)]
+pub struct Replicated<S: StateMachine> {
+    raft: Raft<S::Command>,
+    state: S,
+    /// Commands proposed by this node, by the index of their entry.
+    proposals: HashMap<u64, Proposal>,
+}
+
+impl<S: StateMachine> Replicated<S> {
+    fn tick(&mut self, ctx: &mut Context<Self>) -> anyhow::Result<()> {
+        self.raft.tick(Instant::now());
+
+        self.flush(ctx)
+    }
+
+    /// Sends the messages of Raft, then runs the commands it has committed.
+    fn flush(&mut self, ctx: &mut Context<Self>) -> anyhow::Result<()> {
+        for (peer, msg) in self.raft.take_messages() {
+            ctx.send(peer, S::raft(msg))?;
+        }
+
+        for (index, entry) in self.raft.take_committed() {
+            let proposal = self.proposals.remove(&index);
+
+            let Some(command) = entry.command else {
+                if let Some(proposal) = proposal {
+                    self.lost(ctx, proposal)?;
+                }
+
+                continue;
+            };
+
+            // Every node runs the command, so that they all stay in the same state.
+            let reply = self.state.apply(index, command);
+
+            match proposal {
+                Some(proposal) if proposal.term == entry.term => match reply {
+                    Ok(payload) => ctx.reply(proposal.src, proposal.msg_id, payload)?,
+                    Err(error) => ctx.reply_error(proposal.src, proposal.msg_id, error)?,
+                },
+                Some(proposal) => self.lost(ctx, proposal)?,
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answers a command whose entry was overwritten by another leader, so it never ran.
+    fn lost(&self, ctx: &Context<Self>, proposal: Proposal) -> anyhow::Result<()> {
+        debug!(ctx.log(), "Lost the proposal of term {}", proposal.term);
+
+        ctx.reply_error(
+            proposal.src,
+            proposal.msg_id,
+            Error::new(
+                ErrorCode::TemporarilyUnavailable,
+                "The leader changed before the command committed",
+            ),
+        )
+    }
+
+    /// Sends a command to the leader, and its reply back to the client.
+    fn forward(
+        ctx: &mut Context<Self>,
+        leader: String,
+        src: String,
+        msg_id: Option<u32>,
+        command: S::Command,
+    ) -> anyhow::Result<()> {
+        let options = RpcOptions {
+            timeout: FORWARD_TIMEOUT,
+            retries: 0,
+        };
+
+        ctx.rpc(
+            leader,
+            S::request(command),
+            options,
+            move |_node: &mut Self, ctx, reply| match reply {
+                Ok(reply) => ctx.reply(src, msg_id, reply.body.payload),
+                Err(error) => ctx.reply_error(src, msg_id, error),
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+impl<S: StateMachine> Handler for Replicated<S> {
+    type Payload = S::Payload;
+
+    fn init(ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+        let node = ctx.node();
+        let raft = Raft::new(
+            node.node_id.clone(),
+            &node.node_ids,
+            node.index as u64,
+            Instant::now(),
+        );
+
+        ctx.every("raft", TICK_INTERVAL, Self::tick);
+
+        Ok(Self {
+            raft,
+            state: S::default(),
+            proposals: HashMap::new(),
+        })
+    }
+
+    fn handle(
+        &mut self,
+        ctx: &mut Context<Self>,
+        req: Message<S::Payload>,
+    ) -> anyhow::Result<Option<S::Payload>> {
+        match self.state.decode(req.body.payload) {
+            Decoded::Command(command) => match self.raft.propose(command.clone()) {
+                Ok((index, term)) => {
+                    self.proposals.insert(
+                        index,
+                        Proposal {
+                            term,
+                            src: req.src,
+                            msg_id: req.body.msg_id,
+                        },
+                    );
+
+                    self.flush(ctx)?;
+                }
+                // Only requests of clients are forwarded: a node only forwards to the member it
+                // takes for the leader, so passing its request on would let two members with
+                // different views of the leader send it back and forth.
+                Err(Some(leader)) if !ctx.node().node_ids.contains(&req.src) => {
+                    Self::forward(ctx, leader, req.src, req.body.msg_id, command)?;
+                }
+                Err(Some(leader)) => {
+                    return Err(Error::new(
+                        ErrorCode::TemporarilyUnavailable,
+                        format!("Not the leader, {} is", leader),
+                    )
+                    .into());
+                }
+                Err(None) => {
+                    return Err(Error::new(
+                        ErrorCode::TemporarilyUnavailable,
+                        "No leader is known",
+                    )
+                    .into());
+                }
+            },
+            Decoded::Raft(msg) => {
+                self.raft.step(Instant::now(), &req.src, msg);
+
+                self.flush(ctx)?;
+            }
+            Decoded::Reply(body) => return Ok(Some(body)),
+            Decoded::Other(body) => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maelstrom_core::{harness::Cluster, run_with};
+    use serde::Deserialize;
+    use std::thread;
+
+    use super::*;
+
+    /// A write, marked when a follower forwarded it to the leader.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Write {
+        value: u64,
+        forwarded: bool,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Payload {
+        Write { value: u64 },
+        WriteOk { forwarded: bool },
+        Read,
+        ReadOk { values: Vec<u64> },
+        Forward(Write),
+        Raft(RaftMessage<Write>),
+    }
+
+    /// Every value written, in log order.
+    #[derive(Debug, Default)]
+    struct Values {
+        values: Vec<u64>,
+    }
+
+    impl StateMachine for Values {
+        type Command = Write;
+        type Payload = Payload;
+
+        fn decode(&mut self, payload: Payload) -> Decoded<Write, Payload> {
+            match payload {
+                Payload::Write { value } => Decoded::Command(Write {
+                    value,
+                    forwarded: false,
+                }),
+                Payload::Read => Decoded::Reply(Payload::ReadOk {
+                    values: self.values.clone(),
+                }),
+                Payload::Forward(write) => Decoded::Command(write),
+                Payload::Raft(msg) => Decoded::Raft(msg),
+                payload => Decoded::Other(payload),
+            }
+        }
+
+        fn request(write: Write) -> Payload {
+            Payload::Forward(Write {
+                forwarded: true,
+                ..write
+            })
+        }
+
+        fn raft(msg: RaftMessage<Write>) -> Payload {
+            Payload::Raft(msg)
+        }
+
+        fn apply(&mut self, _index: u64, write: Write) -> Result<Payload, Error> {
+            self.values.push(write.value);
+
+            Ok(Payload::WriteOk {
+                forwarded: write.forwarded,
+            })
+        }
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Returns whether the write was forwarded to the leader.
+    fn write(cluster: &Cluster, node_id: &str, value: u64) -> Result<bool, Error> {
+        match cluster.call(node_id, Payload::Write { value })? {
+            Payload::WriteOk { forwarded } => Ok(forwarded),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    fn read(cluster: &Cluster, node_id: &str) -> Vec<u64> {
+        match cluster.call(node_id, Payload::Read).unwrap() {
+            Payload::ReadOk { values } => values,
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    fn start() -> Cluster {
+        let cluster = Cluster::builder(3)
+            .start(run_with::<Replicated<Values>, _, _>)
+            .unwrap();
+
+        // Writes are refused until a leader is elected.
+        wait_for(|| write(&cluster, "n0", 0).is_ok());
+
+        cluster
+    }
+
+    /// Node that ran a write without forwarding it.
+    fn leader(cluster: &Cluster, value: u64) -> String {
+        cluster
+            .node_ids()
+            .iter()
+            .find(|node_id| !write(cluster, node_id, value).unwrap())
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn followers_forward_writes_to_the_leader() {
+        let cluster = start();
+
+        let forwarded: Vec<bool> = (1..=3)
+            .map(|value| write(&cluster, &format!("n{}", value - 1), value).unwrap())
+            .collect();
+        assert_eq!(forwarded.iter().filter(|forwarded| !**forwarded).count(), 1);
+
+        for node_id in cluster.node_ids() {
+            wait_for(|| read(&cluster, node_id) == vec![0, 1, 2, 3]);
+        }
+
+        cluster.stop().unwrap();
+    }
+
+    #[test]
+    fn a_leader_cut_off_from_the_majority_loses_its_writes() {
+        let cluster = start();
+
+        let leader = leader(&cluster, 1);
+        let majority: Vec<&str> = cluster
+            .node_ids()
+            .iter()
+            .map(String::as_str)
+            .filter(|node_id| *node_id != leader)
+            .collect();
+
+        cluster.partition(&[&[leader.as_str()], &majority]);
+
+        thread::scope(|scope| {
+            // The old leader appends the write, but cannot commit it.
+            let lost = scope.spawn(|| write(&cluster, &leader, 100));
+
+            // The majority elects another leader, and commits writes over it.
+            wait_for(|| write(&cluster, majority[0], 2).is_ok());
+            cluster.heal();
+
+            assert_eq!(
+                lost.join().unwrap().unwrap_err().code,
+                ErrorCode::TemporarilyUnavailable
+            );
+        });
+
+        for node_id in cluster.node_ids() {
+            wait_for(|| read(&cluster, node_id).ends_with(&[2]));
+            assert!(!read(&cluster, node_id).contains(&100));
+        }
+
+        cluster.stop().unwrap();
+    }
+}
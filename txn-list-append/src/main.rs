@@ -0,0 +1,26 @@
+mod node;
+mod op;
+mod snapshot;
+mod strict;
+mod txn;
+
+use node::TxnListAppend;
+use raft::Replicated;
+use snapshot::SnapshotListAppend;
+use std::env;
+use strict::StrictListAppend;
+
+/// Environment variable selecting the consistency of the transactions: `serializable` (default)
+/// runs them on a single node, `snapshot-isolation` runs them on any node against snapshots of a
+/// multi-version store and commits their writes through a Raft log, and `strict-serializable`
+/// runs them all through a Raft log. Both logs need a majority of the nodes.
+const CONSISTENCY_ENV: &str = "TXN_CONSISTENCY";
+
+fn main() -> anyhow::Result<()> {
+    match env::var(CONSISTENCY_ENV).as_deref() {
+        Err(_) | Ok("serializable") => maelstrom_core::run::<TxnListAppend>(),
+        Ok("snapshot-isolation") => maelstrom_core::run::<Replicated<SnapshotListAppend>>(),
+        Ok("strict-serializable") => maelstrom_core::run::<Replicated<StrictListAppend>>(),
+        Ok(consistency) => anyhow::bail!("Unknown {} {}", CONSISTENCY_ENV, consistency),
+    }
+}
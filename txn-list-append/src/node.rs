@@ -0,0 +1,67 @@
+use maelstrom_core::{Context, Error, Handler, Message};
+use raft::RaftMessage;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    op::Op,
+    snapshot::Commit,
+    txn::{self, Lists},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageBody {
+    Txn {
+        txn: Vec<Op>,
+    },
+    TxnOk {
+        txn: Vec<Op>,
+    },
+    /// Messages of the Raft log of the strictly serializable mode.
+    Raft(RaftMessage<Vec<Op>>),
+    /// Writes of a transaction run by the sender, for the leader of the snapshot isolation mode
+    /// to commit.
+    Commit(Commit),
+    /// Messages of the Raft log of the snapshot isolation mode.
+    SnapshotRaft(RaftMessage<Commit>),
+}
+
+/// Lists kept in the memory of a single node.
+///
+/// Messages are handled one at a time, so transactions run one after the other, and they are
+/// serializable.
+#[derive(Debug, Default)]
+pub struct TxnListAppend {
+    lists: Lists,
+}
+
+impl Handler for TxnListAppend {
+    type Payload = MessageBody;
+
+    fn init(_ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let body = match req.body.payload {
+            MessageBody::Txn { txn } => {
+                let executed = txn::execute(&self.lists, txn);
+
+                self.lists.apply(executed.appends);
+
+                MessageBody::TxnOk { txn: executed.txn }
+            }
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        };
+
+        Ok(Some(body))
+    }
+}
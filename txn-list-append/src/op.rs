@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// A micro-operation of a transaction.
+///
+/// On the wire, ops are `["append", key, value]` and `["r", key, list]` arrays. The list of a
+/// read is `null` in the request, and the list read in the reply, still `null` if nothing was
+/// ever appended to the key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawOp", into = "RawOp")]
+pub enum Op {
+    Read { key: u64, list: Option<Vec<u64>> },
+    Append { key: u64, value: u64 },
+}
+
+/// Third element of an op: the appended value, or the list read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Arg {
+    Value(u64),
+    List(Option<Vec<u64>>),
+}
+
+type RawOp = (String, u64, Arg);
+
+impl TryFrom<RawOp> for Op {
+    type Error = String;
+
+    fn try_from((kind, key, arg): RawOp) -> Result<Self, Self::Error> {
+        match (kind.as_str(), arg) {
+            ("r", Arg::List(list)) => Ok(Op::Read { key, list }),
+            ("r", Arg::Value(value)) => Err(format!("Read of {} with a value {}", key, value)),
+            ("append", Arg::Value(value)) => Ok(Op::Append { key, value }),
+            ("append", Arg::List(_)) => Err(format!("Append to {} without a value", key)),
+            (kind, _) => Err(format!("Unknown operation {}", kind)),
+        }
+    }
+}
+
+impl From<Op> for RawOp {
+    fn from(op: Op) -> Self {
+        match op {
+            Op::Read { key, list } => ("r".to_string(), key, Arg::List(list)),
+            Op::Append { key, value } => ("append".to_string(), key, Arg::Value(value)),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use maelstrom_core::{Error, ErrorCode};
+use mvcc::{Mvcc, View};
+use raft::{Decoded, RaftMessage, StateMachine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node::MessageBody,
+    op::Op,
+    txn::{self, Store},
+};
+
+/// Lists written by a transaction that read the snapshot at `snapshot`, for the leader to
+/// commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commit {
+    pub snapshot: u64,
+    pub txn: Vec<Op>,
+    pub writes: Vec<(u64, Vec<u64>)>,
+}
+
+impl Store for View<'_, u64, Vec<u64>> {
+    fn read(&self, key: u64) -> Option<Vec<u64>> {
+        View::read(self, &key).cloned()
+    }
+}
+
+/// Lists kept in a multi-version store, for snapshot isolation.
+///
+/// Run by [`raft::Replicated`] like the registers of the other workload: transactions read a
+/// snapshot of the node that receives them, and their writes are committed through the Raft
+/// log. An append writes a new version of the whole list, so two transactions appending to the
+/// same list conflict.
+#[derive(Debug, Default)]
+pub struct SnapshotListAppend {
+    mvcc: Mvcc<u64, Vec<u64>>,
+}
+
+impl StateMachine for SnapshotListAppend {
+    type Command = Commit;
+    type Payload = MessageBody;
+
+    fn decode(&mut self, payload: MessageBody) -> Decoded<Commit, MessageBody> {
+        match payload {
+            MessageBody::Txn { txn } => {
+                // The snapshot is only read here, so it is given back right away.
+                let snapshot = self.mvcc.begin();
+                let view = self.mvcc.view(&snapshot);
+                let executed = txn::execute(&view, txn);
+
+                let writes: Vec<(u64, Vec<u64>)> = executed
+                    .appends
+                    .into_iter()
+                    .map(|(key, values)| {
+                        let mut list = view.read(&key).cloned().unwrap_or_default();
+                        list.extend(values);
+
+                        (key, list)
+                    })
+                    .collect();
+
+                let ts = snapshot.ts();
+                self.mvcc.abort(snapshot);
+
+                if writes.is_empty() {
+                    return Decoded::Reply(MessageBody::TxnOk { txn: executed.txn });
+                }
+
+                Decoded::Command(Commit {
+                    snapshot: ts,
+                    txn: executed.txn,
+                    writes,
+                })
+            }
+            MessageBody::Commit(commit) => Decoded::Command(commit),
+            MessageBody::SnapshotRaft(msg) => Decoded::Raft(msg),
+            body => Decoded::Other(body),
+        }
+    }
+
+    fn request(commit: Commit) -> MessageBody {
+        MessageBody::Commit(commit)
+    }
+
+    fn raft(msg: RaftMessage<Commit>) -> MessageBody {
+        MessageBody::SnapshotRaft(msg)
+    }
+
+    fn apply(&mut self, _index: u64, commit: Commit) -> Result<MessageBody, Error> {
+        self.mvcc
+            .commit_at(commit.snapshot, commit.writes)
+            .map_err(|conflict| Error::new(ErrorCode::TxnConflict, conflict.to_string()))?;
+
+        Ok(MessageBody::TxnOk { txn: commit.txn })
+    }
+}
@@ -0,0 +1,46 @@
+use maelstrom_core::Error;
+use raft::{Decoded, RaftMessage, StateMachine};
+
+use crate::{
+    node::MessageBody,
+    op::Op,
+    txn::{self, Lists},
+};
+
+/// Lists replicated through a Raft log, for strict serializability.
+///
+/// Run by [`raft::Replicated`]: every transaction, reads included, goes through the log, and
+/// runs on every node once it is committed, so they all end up with the same lists, and a
+/// transaction sees every transaction acknowledged before it was sent.
+#[derive(Debug, Default)]
+pub struct StrictListAppend {
+    lists: Lists,
+}
+
+impl StateMachine for StrictListAppend {
+    type Command = Vec<Op>;
+    type Payload = MessageBody;
+
+    fn decode(&mut self, payload: MessageBody) -> Decoded<Vec<Op>, MessageBody> {
+        match payload {
+            MessageBody::Txn { txn } => Decoded::Command(txn),
+            MessageBody::Raft(msg) => Decoded::Raft(msg),
+            body => Decoded::Other(body),
+        }
+    }
+
+    fn request(txn: Vec<Op>) -> MessageBody {
+        MessageBody::Txn { txn }
+    }
+
+    fn raft(msg: RaftMessage<Vec<Op>>) -> MessageBody {
+        MessageBody::Raft(msg)
+    }
+
+    fn apply(&mut self, _index: u64, txn: Vec<Op>) -> Result<MessageBody, Error> {
+        let executed = txn::execute(&self.lists, txn);
+        self.lists.apply(executed.appends);
+
+        Ok(MessageBody::TxnOk { txn: executed.txn })
+    }
+}
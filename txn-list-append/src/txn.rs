@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::op::Op;
+
+/// Committed lists a transaction reads from.
+pub trait Store {
+    fn read(&self, key: u64) -> Option<Vec<u64>>;
+}
+
+/// A transaction run by [`execute`], not committed yet.
+#[derive(Debug)]
+pub struct Executed {
+    /// Ops of the transaction, completed with the lists read.
+    pub txn: Vec<Op>,
+    /// Values the transaction appended to each list, in order.
+    pub appends: Vec<(u64, Vec<u64>)>,
+}
+
+/// Runs the ops of a transaction in order against `store`.
+///
+/// Appends are kept aside until the caller commits them, so no other transaction can see the
+/// appends of one that has not finished. The transaction itself reads its own appends.
+pub fn execute(store: &impl Store, txn: Vec<Op>) -> Executed {
+    let mut appends: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+
+    let txn = txn
+        .into_iter()
+        .map(|op| match op {
+            Op::Read { key, .. } => {
+                let mut list = store.read(key);
+
+                if let Some(appended) = appends.get(&key) {
+                    list.get_or_insert_default().extend(appended);
+                }
+
+                Op::Read { key, list }
+            }
+            Op::Append { key, value } => {
+                appends.entry(key).or_default().push(value);
+
+                op
+            }
+        })
+        .collect();
+
+    Executed {
+        txn,
+        appends: appends.into_iter().collect(),
+    }
+}
+
+/// Committed list of every key.
+#[derive(Debug, Default)]
+pub struct Lists {
+    values: HashMap<u64, Vec<u64>>,
+}
+
+impl Lists {
+    /// Commits the appends of a transaction.
+    pub fn apply(&mut self, appends: Vec<(u64, Vec<u64>)>) {
+        for (key, values) in appends {
+            self.values.entry(key).or_default().extend(values);
+        }
+    }
+}
+
+impl Store for Lists {
+    fn read(&self, key: u64) -> Option<Vec<u64>> {
+        self.values.get(&key).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append(key: u64, value: u64) -> Op {
+        Op::Append { key, value }
+    }
+
+    fn read(key: u64) -> Op {
+        Op::Read { key, list: None }
+    }
+
+    #[test]
+    fn a_transaction_reads_its_own_appends() {
+        let mut lists = Lists::default();
+        lists.apply(vec![(1, vec![10])]);
+
+        let executed = execute(
+            &lists,
+            vec![
+                read(1),
+                append(1, 11),
+                read(1),
+                append(2, 20),
+                read(2),
+                read(3),
+            ],
+        );
+
+        assert_eq!(
+            executed.txn,
+            vec![
+                Op::Read {
+                    key: 1,
+                    list: Some(vec![10])
+                },
+                append(1, 11),
+                Op::Read {
+                    key: 1,
+                    list: Some(vec![10, 11])
+                },
+                append(2, 20),
+                Op::Read {
+                    key: 2,
+                    list: Some(vec![20])
+                },
+                Op::Read { key: 3, list: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn appends_are_only_seen_once_applied() {
+        let mut lists = Lists::default();
+
+        let executed = execute(&lists, vec![append(1, 10), append(2, 20), append(1, 11)]);
+
+        // Appends are grouped by list, in the order of the transaction.
+        assert_eq!(executed.appends, vec![(1, vec![10, 11]), (2, vec![20])]);
+        assert_eq!(lists.read(1), None);
+
+        lists.apply(executed.appends);
+
+        assert_eq!(lists.read(1), Some(vec![10, 11]));
+        assert_eq!(lists.read(2), Some(vec![20]));
+    }
+
+    #[test]
+    fn transactions_append_in_the_order_they_are_applied() {
+        let mut lists = Lists::default();
+
+        let first = execute(&lists, vec![append(1, 10)]);
+        let second = execute(&lists, vec![append(1, 11), append(1, 12)]);
+
+        lists.apply(first.appends);
+        lists.apply(second.appends);
+
+        assert_eq!(lists.read(1), Some(vec![10, 11, 12]));
+    }
+}
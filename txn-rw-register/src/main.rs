@@ -0,0 +1,29 @@
+mod node;
+mod op;
+mod snapshot;
+mod strict;
+mod txn;
+
+use node::TxnRwRegister;
+use raft::Replicated;
+use snapshot::SnapshotRwRegister;
+use std::env;
+use strict::StrictRwRegister;
+
+/// Environment variable selecting the consistency of the transactions: `read-committed`
+/// (default) runs them on any node and gossips their writes, which keeps every node available,
+/// `snapshot-isolation` runs them on any node against snapshots of a multi-version store and
+/// commits their writes through a Raft log, and `strict-serializable` runs them all through a
+/// Raft log. Both logs need a majority of the nodes.
+const CONSISTENCY_ENV: &str = "TXN_CONSISTENCY";
+
+fn main() -> anyhow::Result<()> {
+    match env::var(CONSISTENCY_ENV).as_deref() {
+        Err(_) | Ok("read-uncommitted") | Ok("read-committed") => {
+            maelstrom_core::run::<TxnRwRegister>()
+        }
+        Ok("snapshot-isolation") => maelstrom_core::run::<Replicated<SnapshotRwRegister>>(),
+        Ok("strict-serializable") => maelstrom_core::run::<Replicated<StrictRwRegister>>(),
+        Ok(consistency) => anyhow::bail!("Unknown {} {}", CONSISTENCY_ENV, consistency),
+    }
+}
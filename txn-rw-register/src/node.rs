@@ -0,0 +1,280 @@
+use maelstrom_core::{Context, Error, Handler, Message, RpcOptions, debug};
+use raft::RaftMessage;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    op::Op,
+    snapshot::Commit,
+    txn::{self, Effects, Registers, Version},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageBody {
+    Txn {
+        txn: Vec<Op>,
+    },
+    TxnOk {
+        txn: Vec<Op>,
+    },
+    /// Effects of transactions run by the sender.
+    Replicate {
+        effects: Vec<Effects>,
+    },
+    ReplicateOk,
+    /// Messages of the Raft log of the strictly serializable mode.
+    Raft(RaftMessage<Vec<Op>>),
+    /// Writes of a transaction run by the sender, for the leader of the snapshot isolation mode
+    /// to commit.
+    Commit(Commit),
+    /// Messages of the Raft log of the snapshot isolation mode.
+    SnapshotRaft(RaftMessage<Commit>),
+}
+
+const REPLICATE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Registers replicated on every node.
+///
+/// Transactions run on the node that receives them, one at a time, and only their committed
+/// writes, the last one of each register, are applied and gossiped to the other nodes, so no
+/// node ever reads the intermediate writes of a transaction. A transaction only reads the
+/// registers of its node, so it cannot fail partway and there are no aborted writes to hide.
+/// Nodes never wait for each other, which keeps every node available under partitions.
+///
+/// Every transaction that writes gets a [`Version`], and a write never replaces the value of a
+/// later transaction, so concurrent writes converge to the same values on every node.
+#[derive(Debug, Default)]
+pub struct TxnRwRegister {
+    registers: Registers,
+    /// Lamport clock, ahead of the version of every transaction this node has seen.
+    clock: u64,
+    /// Effects of the transactions run by this node that a peer has not acknowledged yet.
+    unreplicated: HashMap<String, Vec<Effects>>,
+}
+
+impl TxnRwRegister {
+    fn next_version(&mut self, ctx: &Context<Self>) -> Version {
+        self.clock += 1;
+
+        (self.clock, ctx.node().index)
+    }
+
+    /// Sends the unacknowledged effects to every peer.
+    fn replicate(&mut self, ctx: &mut Context<Self>) -> anyhow::Result<()> {
+        // A batch that times out is not retried, the next tick sends it again along with
+        // whatever was run in the meantime.
+        let options = RpcOptions {
+            timeout: REPLICATE_INTERVAL,
+            retries: 0,
+        };
+
+        for (peer, effects) in self.unreplicated.iter() {
+            if effects.is_empty() {
+                continue;
+            }
+
+            debug!(
+                ctx.log(),
+                "Replicating {} transactions to {}",
+                effects.len(),
+                peer
+            );
+
+            let sent: Vec<Version> = effects.iter().map(|effects| effects.version).collect();
+            let peer = peer.clone();
+
+            ctx.rpc(
+                peer.clone(),
+                MessageBody::Replicate {
+                    effects: effects.clone(),
+                },
+                options,
+                move |node: &mut Self, _ctx, reply| {
+                    if reply.is_ok()
+                        && let Some(effects) = node.unreplicated.get_mut(&peer)
+                    {
+                        effects.retain(|effects| !sent.contains(&effects.version));
+                    }
+
+                    Ok(())
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for TxnRwRegister {
+    type Payload = MessageBody;
+
+    fn init(ctx: &mut Context<Self>) -> anyhow::Result<Self> {
+        ctx.every("replicate", REPLICATE_INTERVAL, Self::replicate);
+
+        Ok(Self {
+            unreplicated: ctx
+                .node()
+                .peers()
+                .map(|peer| (peer.clone(), Vec::new()))
+                .collect(),
+            ..Self::default()
+        })
+    }
+
+    fn handle(
+        &mut self,
+        ctx: &mut Context<Self>,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let body = match req.body.payload {
+            MessageBody::Txn { txn } => {
+                let executed = txn::execute(&self.registers, txn);
+
+                if !executed.writes.is_empty() {
+                    let effects = Effects {
+                        version: self.next_version(ctx),
+                        writes: executed.writes,
+                    };
+
+                    self.registers.apply(&effects);
+
+                    for pending in self.unreplicated.values_mut() {
+                        pending.push(effects.clone());
+                    }
+                }
+
+                Some(MessageBody::TxnOk { txn: executed.txn })
+            }
+            MessageBody::Replicate { effects } => {
+                for effects in effects.iter() {
+                    self.clock = self.clock.max(effects.version.0);
+                    self.registers.apply(effects);
+                }
+
+                Some(MessageBody::ReplicateOk)
+            }
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        };
+
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maelstrom_core::{harness::Cluster, run_with};
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Instant,
+    };
+
+    use super::*;
+
+    fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while !condition() {
+            assert!(Instant::now() < deadline, "Timed out waiting");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn txn(cluster: &Cluster, node_id: &str, txn: Vec<Op>) -> Vec<Op> {
+        match cluster.call(node_id, MessageBody::Txn { txn }).unwrap() {
+            MessageBody::TxnOk { txn } => txn,
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    fn read(cluster: &Cluster, node_id: &str, key: u64) -> Option<u64> {
+        match txn(cluster, node_id, vec![Op::Read { key, value: None }])[..] {
+            [Op::Read { value, .. }] => value,
+            ref ops => panic!("Unexpected ops {:?}", ops),
+        }
+    }
+
+    fn start() -> Cluster {
+        Cluster::builder(3)
+            .start(run_with::<TxnRwRegister, _, _>)
+            .unwrap()
+    }
+
+    #[test]
+    fn writes_reach_the_other_side_of_a_partition_once_healed() {
+        let cluster = start();
+
+        cluster.partition(&[&["n0"], &["n1", "n2"]]);
+
+        // Both sides stay available.
+        txn(&cluster, "n0", vec![Op::Write { key: 1, value: 10 }]);
+        txn(&cluster, "n2", vec![Op::Write { key: 2, value: 20 }]);
+        assert_eq!(read(&cluster, "n0", 1), Some(10));
+        assert_eq!(read(&cluster, "n2", 1), None);
+
+        cluster.heal();
+
+        wait_for(|| read(&cluster, "n2", 1) == Some(10));
+        wait_for(|| read(&cluster, "n0", 2) == Some(20));
+
+        cluster.stop().unwrap();
+    }
+
+    #[test]
+    fn intermediate_writes_are_never_read() {
+        let cluster = start();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            // Every transaction overwrites the odd value it writes with an even one.
+            scope.spawn(|| {
+                for i in 0..50 {
+                    txn(
+                        &cluster,
+                        "n0",
+                        vec![
+                            Op::Write {
+                                key: 1,
+                                value: 2 * i + 1,
+                            },
+                            Op::Write {
+                                key: 1,
+                                value: 2 * i + 2,
+                            },
+                        ],
+                    );
+                }
+
+                done.store(true, Ordering::Relaxed);
+            });
+
+            for node_id in cluster.node_ids() {
+                let (cluster, done) = (&cluster, &done);
+
+                scope.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let value = read(cluster, node_id, 1);
+
+                        assert!(
+                            value.is_none_or(|value| value % 2 == 0),
+                            "{} read {:?}",
+                            node_id,
+                            value
+                        );
+                    }
+                });
+            }
+        });
+
+        for node_id in cluster.node_ids() {
+            wait_for(|| read(&cluster, node_id, 1) == Some(100));
+        }
+
+        cluster.stop().unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// A micro-operation of a transaction.
+///
+/// On the wire, ops are `["r", key, value]` and `["w", key, value]` arrays. The value of a read
+/// is `null` in the request, and the value read in the reply, still `null` if the key was never
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawOp", into = "RawOp")]
+pub enum Op {
+    Read { key: u64, value: Option<u64> },
+    Write { key: u64, value: u64 },
+}
+
+type RawOp = (String, u64, Option<u64>);
+
+impl TryFrom<RawOp> for Op {
+    type Error = String;
+
+    fn try_from((kind, key, value): RawOp) -> Result<Self, Self::Error> {
+        match (kind.as_str(), value) {
+            ("r", value) => Ok(Op::Read { key, value }),
+            ("w", Some(value)) => Ok(Op::Write { key, value }),
+            ("w", None) => Err(format!("Write of {} without a value", key)),
+            (kind, _) => Err(format!("Unknown operation {}", kind)),
+        }
+    }
+}
+
+impl From<Op> for RawOp {
+    fn from(op: Op) -> Self {
+        match op {
+            Op::Read { key, value } => ("r".to_string(), key, value),
+            Op::Write { key, value } => ("w".to_string(), key, Some(value)),
+        }
+    }
+}
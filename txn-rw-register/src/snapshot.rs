@@ -0,0 +1,207 @@
+use maelstrom_core::{Error, ErrorCode};
+use mvcc::{Mvcc, View};
+use raft::{Decoded, RaftMessage, StateMachine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node::MessageBody,
+    op::Op,
+    txn::{self, Store},
+};
+
+/// Writes of a transaction that read the snapshot at `snapshot`, for the leader to commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commit {
+    pub snapshot: u64,
+    pub txn: Vec<Op>,
+    pub writes: Vec<(u64, u64)>,
+}
+
+impl Store for View<'_, u64, u64> {
+    fn read(&self, key: u64) -> Option<u64> {
+        View::read(self, &key).copied()
+    }
+}
+
+/// Registers kept in a multi-version store, for snapshot isolation.
+///
+/// Run by [`raft::Replicated`]: a transaction reads from a snapshot of the node that receives
+/// it, which may lag behind the leader, and answers right away if it writes nothing. Otherwise
+/// its writes go through the Raft log, and every node commits them on top of that snapshot, in
+/// log order. Transactions that write are in flight for as long as it takes to commit an entry,
+/// so they overlap: when two of them write the same register, the first one in the log wins and
+/// the other one aborts with `txn-conflict`.
+#[derive(Debug, Default)]
+pub struct SnapshotRwRegister {
+    mvcc: Mvcc<u64, u64>,
+}
+
+impl StateMachine for SnapshotRwRegister {
+    type Command = Commit;
+    type Payload = MessageBody;
+
+    fn decode(&mut self, payload: MessageBody) -> Decoded<Commit, MessageBody> {
+        match payload {
+            MessageBody::Txn { txn } => {
+                // The snapshot is only read here, so it is given back right away.
+                let snapshot = self.mvcc.begin();
+                let executed = txn::execute(&self.mvcc.view(&snapshot), txn);
+                let ts = snapshot.ts();
+                self.mvcc.abort(snapshot);
+
+                if executed.writes.is_empty() {
+                    return Decoded::Reply(MessageBody::TxnOk { txn: executed.txn });
+                }
+
+                Decoded::Command(Commit {
+                    snapshot: ts,
+                    txn: executed.txn,
+                    writes: executed.writes,
+                })
+            }
+            MessageBody::Commit(commit) => Decoded::Command(commit),
+            MessageBody::SnapshotRaft(msg) => Decoded::Raft(msg),
+            body => Decoded::Other(body),
+        }
+    }
+
+    fn request(commit: Commit) -> MessageBody {
+        MessageBody::Commit(commit)
+    }
+
+    fn raft(msg: RaftMessage<Commit>) -> MessageBody {
+        MessageBody::SnapshotRaft(msg)
+    }
+
+    fn apply(&mut self, _index: u64, commit: Commit) -> Result<MessageBody, Error> {
+        self.mvcc
+            .commit_at(commit.snapshot, commit.writes)
+            .map_err(|conflict| Error::new(ErrorCode::TxnConflict, conflict.to_string()))?;
+
+        Ok(MessageBody::TxnOk { txn: commit.txn })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maelstrom_core::{harness::Cluster, run_with};
+    use raft::Replicated;
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    fn command(state: &mut SnapshotRwRegister, txn: Vec<Op>) -> Commit {
+        match state.decode(MessageBody::Txn { txn }) {
+            Decoded::Command(commit) => commit,
+            decoded => panic!("Expected a commit, got {:?}", decoded),
+        }
+    }
+
+    fn write(key: u64, value: u64) -> Op {
+        Op::Write { key, value }
+    }
+
+    fn read(key: u64) -> Op {
+        Op::Read { key, value: None }
+    }
+
+    #[test]
+    fn reads_are_answered_from_the_last_commit() {
+        let mut state = SnapshotRwRegister::default();
+
+        let commit = command(&mut state, vec![write(1, 10)]);
+        state.apply(1, commit).unwrap();
+
+        match state.decode(MessageBody::Txn {
+            txn: vec![read(1), read(2)],
+        }) {
+            Decoded::Reply(MessageBody::TxnOk { txn }) => assert_eq!(
+                txn,
+                vec![
+                    Op::Read {
+                        key: 1,
+                        value: Some(10)
+                    },
+                    Op::Read {
+                        key: 2,
+                        value: None
+                    }
+                ]
+            ),
+            decoded => panic!("Expected a reply, got {:?}", decoded),
+        }
+    }
+
+    #[test]
+    fn overlapping_writes_to_a_register_conflict() {
+        let mut state = SnapshotRwRegister::default();
+
+        // Both run before either is committed, as when they wait for the log.
+        let first = command(&mut state, vec![write(1, 10)]);
+        let second = command(&mut state, vec![read(2), write(1, 11)]);
+        let other = command(&mut state, vec![write(2, 20)]);
+
+        assert!(state.apply(1, first).is_ok());
+        assert_eq!(
+            state.apply(2, second).unwrap_err().code,
+            ErrorCode::TxnConflict
+        );
+        assert!(state.apply(3, other).is_ok());
+
+        // A transaction that started after the winner committed does not conflict.
+        let later = command(&mut state, vec![write(1, 12)]);
+        assert!(state.apply(4, later).is_ok());
+    }
+
+    fn txn(cluster: &Cluster, node_id: &str, txn: Vec<Op>) -> Result<Vec<Op>, Error> {
+        match cluster.call(node_id, MessageBody::Txn { txn })? {
+            MessageBody::TxnOk { txn } => Ok(txn),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn concurrent_writers_through_the_log_conflict() {
+        let cluster = Cluster::builder(3)
+            .start(run_with::<Replicated<SnapshotRwRegister>, _, _>)
+            .unwrap();
+
+        // Writes are refused until a leader is elected.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while txn(&cluster, "n0", vec![write(1, 0)]).is_err() {
+            assert!(Instant::now() < deadline, "Timed out waiting for a leader");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Two transactions sent at the same time read their snapshots before either commits,
+        // one of them on a follower that forwards its writes to the leader.
+        let conflicted = (1..=20).any(|round| {
+            let (first, second) = thread::scope(|scope| {
+                let first = scope.spawn(|| txn(&cluster, "n0", vec![write(1, round * 2)]));
+                let second = scope.spawn(|| txn(&cluster, "n1", vec![write(1, round * 2 + 1)]));
+
+                (first.join().unwrap(), second.join().unwrap())
+            });
+
+            match (first, second) {
+                (Ok(_), Err(error)) | (Err(error), Ok(_)) => {
+                    assert_eq!(error.code, ErrorCode::TxnConflict);
+
+                    true
+                }
+                (first, second) => {
+                    assert!(first.is_ok() && second.is_ok(), "Both writers failed");
+
+                    false
+                }
+            }
+        });
+
+        assert!(conflicted, "No write conflicted");
+
+        cluster.stop().unwrap();
+    }
+}
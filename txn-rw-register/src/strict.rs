@@ -0,0 +1,113 @@
+use maelstrom_core::Error;
+use raft::{Decoded, RaftMessage, StateMachine};
+
+use crate::{
+    node::MessageBody,
+    op::Op,
+    txn::{self, Effects, Registers},
+};
+
+/// Registers replicated through a Raft log, for strict serializability.
+///
+/// Run by [`raft::Replicated`]: every transaction, reads included, goes through the log, and
+/// runs on every node once it is committed, so they all end up with the same registers, and a
+/// transaction sees every transaction acknowledged before it was sent. Unlike the other modes,
+/// this one is not available under partitions.
+#[derive(Debug, Default)]
+pub struct StrictRwRegister {
+    registers: Registers,
+}
+
+impl StateMachine for StrictRwRegister {
+    type Command = Vec<Op>;
+    type Payload = MessageBody;
+
+    fn decode(&mut self, payload: MessageBody) -> Decoded<Vec<Op>, MessageBody> {
+        match payload {
+            MessageBody::Txn { txn } => Decoded::Command(txn),
+            MessageBody::Raft(msg) => Decoded::Raft(msg),
+            body => Decoded::Other(body),
+        }
+    }
+
+    fn request(txn: Vec<Op>) -> MessageBody {
+        MessageBody::Txn { txn }
+    }
+
+    fn raft(msg: RaftMessage<Vec<Op>>) -> MessageBody {
+        MessageBody::Raft(msg)
+    }
+
+    fn apply(&mut self, index: u64, txn: Vec<Op>) -> Result<MessageBody, Error> {
+        let executed = txn::execute(&self.registers, txn);
+
+        // The index of the entry orders the transactions the same way on every node.
+        self.registers.apply(&Effects {
+            version: (index, 0),
+            writes: executed.writes,
+        });
+
+        Ok(MessageBody::TxnOk { txn: executed.txn })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maelstrom_core::{harness::Cluster, run_with};
+    use raft::Replicated;
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    fn txn(cluster: &Cluster, node_id: &str, txn: Vec<Op>) -> Result<Vec<Op>, Error> {
+        match cluster.call(node_id, MessageBody::Txn { txn })? {
+            MessageBody::TxnOk { txn } => Ok(txn),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn acknowledged_writes_are_read_on_every_node() {
+        let cluster = Cluster::builder(3)
+            .start(run_with::<Replicated<StrictRwRegister>, _, _>)
+            .unwrap();
+
+        // Transactions are refused until a leader is elected.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while txn(&cluster, "n0", vec![Op::Write { key: 1, value: 0 }]).is_err() {
+            assert!(Instant::now() < deadline, "Timed out waiting for a leader");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        for (value, (writer, reader)) in [("n0", "n1"), ("n1", "n2"), ("n2", "n0")]
+            .into_iter()
+            .enumerate()
+        {
+            let value = value as u64 + 1;
+            txn(&cluster, writer, vec![Op::Write { key: 1, value }]).unwrap();
+
+            // Reads go through the log too, so they see every acknowledged write right away.
+            let read = txn(
+                &cluster,
+                reader,
+                vec![Op::Read {
+                    key: 1,
+                    value: None,
+                }],
+            )
+            .unwrap();
+            assert_eq!(
+                read,
+                vec![Op::Read {
+                    key: 1,
+                    value: Some(value)
+                }]
+            );
+        }
+
+        cluster.stop().unwrap();
+    }
+}
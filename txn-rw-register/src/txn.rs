@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::op::Op;
+
+/// Committed registers a transaction reads from.
+pub trait Store {
+    fn read(&self, key: u64) -> Option<u64>;
+}
+
+/// A transaction run by [`execute`], not committed yet.
+#[derive(Debug)]
+pub struct Executed {
+    /// Ops of the transaction, completed with the values read.
+    pub txn: Vec<Op>,
+    /// Last value the transaction wrote to each register.
+    pub writes: Vec<(u64, u64)>,
+}
+
+/// Runs the ops of a transaction in order against `store`.
+///
+/// Writes are kept aside until the caller commits them, and only the last one of each register
+/// is kept, so no other transaction can see the writes of one that has not finished. The
+/// transaction itself reads its own writes.
+pub fn execute(store: &impl Store, txn: Vec<Op>) -> Executed {
+    let mut writes = BTreeMap::new();
+
+    let txn = txn
+        .into_iter()
+        .map(|op| match op {
+            Op::Read { key, .. } => {
+                let value = match writes.get(&key) {
+                    Some(value) => Some(*value),
+                    None => store.read(key),
+                };
+
+                Op::Read { key, value }
+            }
+            Op::Write { key, value } => {
+                writes.insert(key, value);
+
+                op
+            }
+        })
+        .collect();
+
+    Executed {
+        txn,
+        writes: writes.into_iter().collect(),
+    }
+}
+
+/// Position of a transaction in the order every node agrees on: the Lamport clock of the node
+/// that ran it, then the index of that node to break ties.
+pub type Version = (u64, usize);
+
+/// Committed writes of a transaction, as replicated to the other nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Effects {
+    pub version: Version,
+    pub writes: Vec<(u64, u64)>,
+}
+
+/// Registers whose writes are ordered by the version of their transaction.
+///
+/// A write only replaces a value from an earlier transaction, so the nodes end up with the same
+/// values whatever order they apply the effects of concurrent transactions in, and the order of
+/// the writes of every register follows the order of the transactions, which rules out write
+/// cycles.
+#[derive(Debug, Default)]
+pub struct Registers {
+    values: HashMap<u64, (Version, u64)>,
+}
+
+impl Registers {
+    /// Commits the effects of a transaction. Committing them again has no effect.
+    pub fn apply(&mut self, effects: &Effects) {
+        for (key, value) in effects.writes.iter() {
+            if self
+                .values
+                .get(key)
+                .is_none_or(|(version, _)| *version < effects.version)
+            {
+                self.values.insert(*key, (effects.version, *value));
+            }
+        }
+    }
+}
+
+impl Store for Registers {
+    fn read(&self, key: u64) -> Option<u64> {
+        self.values.get(&key).map(|(_, value)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(key: u64, value: u64) -> Op {
+        Op::Write { key, value }
+    }
+
+    fn read(key: u64, value: Option<u64>) -> Op {
+        Op::Read { key, value }
+    }
+
+    #[test]
+    fn a_transaction_reads_its_own_writes() {
+        let mut registers = Registers::default();
+        registers.apply(&Effects {
+            version: (1, 0),
+            writes: vec![(1, 10)],
+        });
+
+        let executed = execute(
+            &registers,
+            vec![read(1, None), write(1, 11), read(1, None), read(2, None)],
+        );
+
+        assert_eq!(
+            executed.txn,
+            vec![
+                read(1, Some(10)),
+                write(1, 11),
+                read(1, Some(11)),
+                read(2, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_last_write_of_a_register_is_committed() {
+        let registers = Registers::default();
+
+        let executed = execute(&registers, vec![write(1, 10), write(2, 20), write(1, 11)]);
+
+        assert_eq!(executed.writes, vec![(1, 11), (2, 20)]);
+        assert_eq!(registers.read(1), None);
+    }
+
+    #[test]
+    fn later_versions_win_whatever_the_order_of_the_effects() {
+        let first = Effects {
+            version: (1, 1),
+            writes: vec![(1, 10), (2, 20)],
+        };
+        let second = Effects {
+            version: (2, 0),
+            writes: vec![(1, 11)],
+        };
+
+        let mut in_order = Registers::default();
+        in_order.apply(&first);
+        in_order.apply(&second);
+
+        let mut reversed = Registers::default();
+        reversed.apply(&second);
+        reversed.apply(&first);
+        reversed.apply(&first);
+
+        for registers in [in_order, reversed] {
+            assert_eq!(registers.read(1), Some(11));
+            assert_eq!(registers.read(2), Some(20));
+        }
+    }
+}
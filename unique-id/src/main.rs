@@ -1,35 +1,18 @@
 mod node;
+mod tso;
 
-use anyhow::Context;
-use node::{Message, Node};
-use std::io::{self};
+use node::UniqueId;
+use std::env;
+use tso::TsoId;
 
-use crate::node::NodeState;
+/// Environment variable selecting how ids are generated: `sequence` (default) pairs the node id
+/// with a local sequence, `tso` takes every id from Maelstrom's `lin-tso`.
+const STRATEGY_ENV: &str = "UNIQUE_ID_STRATEGY";
 
 fn main() -> anyhow::Result<()> {
-    let mut state = NodeState::default();
-    let mut first_line = String::new();
-
-    // The first line must be a init, otherwise it returns an error.
-    let mut node = match io::stdin().read_line(&mut first_line) {
-        Ok(_) => Node::init(first_line, &mut state)?,
-        Err(_) => {
-            panic!("Init message is required")
-        }
-    };
-
-    let lines = io::stdin().lines();
-
-    for line in lines {
-        let content = line?;
-
-        let req: Message =
-            serde_json::from_str(&content).context("Message deserialization error")?;
-
-        if let Some(res) = node.handle(req)? {
-            node.write(res)?;
-        }
+    match env::var(STRATEGY_ENV).as_deref() {
+        Err(_) | Ok("sequence") => maelstrom_core::run::<UniqueId>(),
+        Ok("tso") => maelstrom_core::async_runtime::run::<TsoId>(),
+        Ok(strategy) => anyhow::bail!("Unknown {} {}", STRATEGY_ENV, strategy),
     }
-
-    Ok(())
 }
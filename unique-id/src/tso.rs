@@ -0,0 +1,43 @@
+use maelstrom_core::{
+    Error, Message,
+    async_runtime::{Context, Handler},
+    services::Tso,
+};
+
+use crate::node::MessageBody;
+
+/// Takes every id from Maelstrom's `lin-tso`, whose timestamps are never handed out twice.
+///
+/// Ids stay unique without the node id in them, but no id can be generated while the oracle is
+/// unreachable.
+#[derive(Debug, Default)]
+pub struct TsoId {
+    tso: Tso,
+}
+
+impl Handler for TsoId {
+    type Payload = MessageBody;
+
+    fn init(_ctx: &Context) -> anyhow::Result<Self> {
+        Ok(Self::default())
+    }
+
+    async fn handle(
+        &self,
+        ctx: Context,
+        req: Message<MessageBody>,
+    ) -> anyhow::Result<Option<MessageBody>> {
+        let body = match req.body.payload {
+            MessageBody::Generate => MessageBody::GenerateOk {
+                id: self.tso.ts(&ctx).await?.to_string(),
+            },
+            body => {
+                return Err(
+                    Error::not_supported(format!("Message {:?} not supported", body)).into(),
+                );
+            }
+        };
+
+        Ok(Some(body))
+    }
+}